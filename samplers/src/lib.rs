@@ -28,14 +28,29 @@ pub struct SampleSource {
     pub rng: IsaacRng,
 }
 
+// A source seeded from the thread RNG. Use seeded() instead where
+// results need to be reproducible.
 pub fn new() -> SampleSource {
     let mut trng = rand::thread_rng();
+    seeded(trng.gen())
+}
 
+pub fn seeded(seed: u64) -> SampleSource {
     SampleSource {
-        rng: IsaacRng::new_from_u64(trng.gen())
+        rng: IsaacRng::new_from_u64(seed)
     }
 }
 
+// Combine a seed with a key to get the seed for an independent stream,
+// e.g. one per image row. Uses the SplitMix64 finalizer so that nearby
+// keys give unrelated seeds.
+pub fn derive_seed(seed: u64, key: u64) -> u64 {
+    let mut z = seed.wrapping_add(key.wrapping_add(1).wrapping_mul(0x9e3779b97f4a7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
 pub fn u_grid_regular(root: usize) -> Vec<UnitSquareSample> {
     let increment = 1.0 / (root as f64);
     let start = 0.5 * increment;
@@ -56,6 +71,18 @@ pub fn u_grid_jittered(s: &mut SampleSource, root: usize) -> Vec<UnitSquareSampl
         }).collect()
 }
 
+// count jittered samples in [0..1], one per equal-width stratum, in
+// random order so that they don't correlate with the order of other
+// sample sets.
+pub fn u_line_jittered(s: &mut SampleSource, count: usize) -> Vec<f64> {
+    let between = Uniform::from(0.0..1.0);
+    let increment = 1.0 / (count as f64);
+    let mut samples: Vec<f64> = (0..count).map(
+        |i| (i as f64 + between.sample(&mut s.rng)) * increment).collect();
+    s.rng.shuffle(&mut samples);
+    samples
+}
+
 // Assumes input samples are all in [0..1]
 pub fn to_hemisphere(points: Vec<UnitSquareSample>, e: f64) -> Vec<Vector3<f64>> {
     points.iter().map(
@@ -74,41 +101,44 @@ pub fn to_hemisphere(points: Vec<UnitSquareSample>, e: f64) -> Vec<Vector3<f64>>
 
 // Assumes input samples are all in [0..1]
 pub fn to_poisson_disc(points: Vec<UnitSquareSample>) -> Vec<UnitDiscSample> {
-    points.iter().map(
-        |p| {
-            let spx = 2.0 * p.x - 1.0;
-            let spy = 2.0 * p.y - 1.0;
-            let mut phi: f64;
-            let r: f64;
-
-            if spx > -spy {
-                if spx > spy {
-                    r = spx;
-                    phi = spy / spx;
-                } else {
-                    r = spy;
-                    phi = 2.0 - spx / spy;
-                }
+    points.iter().map(|p| to_unit_disc(p.x, p.y)).collect()
+}
+
+// Map a point in [0..1] x [0..1] onto the unit disc with Shirley's
+// concentric mapping, which preserves the stratification of the
+// input.
+pub fn to_unit_disc(x: f64, y: f64) -> UnitDiscSample {
+    let spx = 2.0 * x - 1.0;
+    let spy = 2.0 * y - 1.0;
+    let mut phi: f64;
+    let r: f64;
+
+    if spx > -spy {
+        if spx > spy {
+            r = spx;
+            phi = spy / spx;
+        } else {
+            r = spy;
+            phi = 2.0 - spx / spy;
+        }
+    } else {
+        if spx < spy {
+            r = -spx;
+            phi = 4.0 + spy / spx;
+        } else {
+            r = -spy;
+            if spy != 0.0 {
+                phi = 6.0 - spx / spy;
             } else {
-                if spx < spy {
-                    r = -spx;
-                    phi = 4.0 + spy / spx;
-                } else {
-                    r = -spy;
-                    if spy != 0.0 {
-                        phi = 6.0 - spx / spy;
-                    } else {
-                        phi = 0.0;
-                    }
-                }
+                phi = 0.0;
             }
+        }
+    }
 
-            phi *= std::f64::consts::PI / 4.0;
+    phi *= std::f64::consts::PI / 4.0;
 
-            UnitDiscSample {
-                x: r * phi.cos(),
-                y: r * phi.sin(),
-            }
-        }
-        ).collect()
+    UnitDiscSample {
+        x: r * phi.cos(),
+        y: r * phi.sin(),
+    }
 }
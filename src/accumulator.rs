@@ -0,0 +1,69 @@
+
+use types::*;
+use tiles::Tile;
+
+// Running per-pixel sums of rendered samples. The current estimate of
+// each pixel is its sum divided by the number of samples taken so far,
+// so the image can be read out at any point during a render.
+pub struct Accumulator {
+    pub width: usize,
+    pub height: usize,
+    pub sums: Vec<Color>,
+    pub counts: Vec<usize>,
+}
+
+impl Accumulator {
+    pub fn new(width: usize, height: usize) -> Accumulator {
+        Accumulator {
+            width,
+            height,
+            sums: vec![black(); width * height],
+            counts: vec![0; width * height],
+        }
+    }
+
+    // Add the sample sums for a tile, given row by row, where each
+    // pixel received the same number of samples.
+    pub fn add_tile(&mut self, tile: &Tile, sums: &Vec<Color>, samples: usize) {
+        for (i, sum) in sums.iter().enumerate() {
+            let p = (tile.y0 + i / tile.width()) * self.width + tile.x0 + i % tile.width();
+            self.sums[p] += *sum;
+            self.counts[p] += samples;
+        }
+    }
+
+    // The number of samples accumulated so far for a pixel.
+    pub fn samples_at(&self, x: usize, y: usize) -> usize {
+        self.counts[y * self.width + x]
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        let p = y * self.width + x;
+        if self.counts[p] == 0 {
+            black()
+        } else {
+            let mut color = self.sums[p];
+            color *= 1.0 / (self.counts[p] as f64);
+            color.max_to_one();
+            color
+        }
+    }
+
+    pub fn tile_pixels(&self, tile: &Tile) -> Vec<Color> {
+        let mut pixels = Vec::with_capacity(tile.pixel_count());
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                pixels.push(self.pixel(x, y));
+            }
+        }
+        pixels
+    }
+
+    pub fn image(&self) -> Image {
+        let mut img = Image::new(self.width, self.height);
+        for y in 0..self.height {
+            img.set_row(y, (0..self.width).map(|x| self.pixel(x, y)).collect());
+        }
+        img
+    }
+}
@@ -0,0 +1,138 @@
+
+extern crate nalgebra;
+use nalgebra::{Vector3};
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+
+use types::*;
+use util;
+
+// A camera position at a given frame.
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Keyframe {
+    pub frame: usize,
+    pub eye: Vector3<f64>,
+    pub look_at: Vector3<f64>,
+    pub up: Vector3<f64>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub enum Motion {
+    // One full orbit of the eye around the scene camera's look_at
+    // point, about its up vector.
+    Turntable,
+    // Linear interpolation between keyframes, which must be sorted by
+    // frame. Frames before the first keyframe or after the last hold
+    // its position.
+    Keyframes(Vec<Keyframe>),
+}
+
+// A sequence of frames rendered with the scene camera moved by motion.
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Animation {
+    pub frames: usize,
+    pub motion: Motion,
+}
+
+impl Animation {
+    pub fn describe(&self) -> String {
+        match self.motion {
+            Motion::Turntable => format!("{} frames, turntable", self.frames),
+            Motion::Keyframes(ref k) => format!("{} frames, {} keyframes", self.frames, k.len()),
+        }
+    }
+
+    // The camera core for a frame, given the scene camera's own core.
+    // Shutter settings are kept from the scene camera.
+    pub fn core_at(&self, base: &CameraCore, frame: usize) -> CameraCore {
+        let (eye, look_at, up) = match self.motion {
+            Motion::Turntable => {
+                let angle = 2.0 * PI * frame as f64 / self.frames as f64;
+                let eye = rotate(&(base.eye - base.look_at), &base.up.normalize(), angle);
+                (base.look_at + eye, base.look_at, base.up)
+            },
+            Motion::Keyframes(ref keys) => interpolate(keys, frame),
+        };
+
+        CameraCore::new(eye, look_at, up).with_shutter(base.shutter_open, base.shutter_close)
+    }
+}
+
+// Rotate v by angle radians about the unit vector axis (Rodrigues'
+// rotation formula).
+fn rotate(v: &Vector3<f64>, axis: &Vector3<f64>, angle: f64) -> Vector3<f64> {
+    v * angle.cos() + axis.cross(v) * angle.sin() + axis * axis.dot(v) * (1.0 - angle.cos())
+}
+
+fn interpolate(keys: &Vec<Keyframe>, frame: usize)
+               -> (Vector3<f64>, Vector3<f64>, Vector3<f64>) {
+    let next = keys.iter().position(|k| k.frame > frame).unwrap_or(keys.len());
+    if next == 0 {
+        let k = &keys[0];
+        return (k.eye, k.look_at, k.up);
+    }
+
+    let a = &keys[next - 1];
+    if next == keys.len() {
+        return (a.eye, a.look_at, a.up);
+    }
+
+    let b = &keys[next];
+    let t = (frame - a.frame) as f64 / (b.frame - a.frame) as f64;
+    let lerp = |x: &Vector3<f64>, y: &Vector3<f64>| x + (y - x) * t;
+    (lerp(&a.eye, &b.eye), lerp(&a.look_at, &b.look_at), lerp(&a.up, &b.up))
+}
+
+// Read keyframes from a text file with one keyframe per line: the frame
+// number, then the eye, look_at and up vectors, all separated by
+// whitespace. Blank lines and lines starting with # are ignored. The
+// result is sorted by frame.
+pub fn load_keyframes(path: &str) -> io::Result<Vec<Keyframe>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut keys = vec![];
+
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData,
+                                        format!("{}:{}: expected a frame number and 9 coordinates",
+                                                path, n + 1));
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 10 {
+            return Err(invalid());
+        }
+        let v: Vec<f64> = fields[1..].iter().map(|f| f.parse().map_err(|_| invalid()))
+            .collect::<io::Result<Vec<f64>>>()?;
+
+        keys.push(Keyframe {
+            frame: fields[0].parse().map_err(|_| invalid())?,
+            eye: Vector3::new(v[0], v[1], v[2]),
+            look_at: Vector3::new(v[3], v[4], v[5]),
+            up: Vector3::new(v[6], v[7], v[8]),
+        });
+    }
+
+    if keys.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("{}: no keyframes", path)));
+    }
+
+    keys.sort_by_key(|k| k.frame);
+    Ok(keys)
+}
+
+// The output path for a frame: the frame number, zero-padded to four
+// digits, inserted before the extension, e.g. output.0012.ppm.
+pub fn frame_path(path: &str, frame: usize) -> String {
+    util::path_with_suffix(path, &format!("{:04}", frame))
+}
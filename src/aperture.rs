@@ -0,0 +1,224 @@
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+
+use samplers;
+
+// The shape of a lens aperture, which gives out-of-focus highlights
+// their shape. Apertures map lens samples in [0..1] x [0..1] to points
+// in [-1..1] x [-1..1], which cameras scale by their lens radius.
+#[derive(Clone)]
+pub enum Aperture {
+    Circle,
+    // A regular polygon with the given number of blades inscribed in
+    // the unit circle, rotated counterclockwise by rotation degrees.
+    // Build with Aperture::polygon, which checks the blade count.
+    Polygon { blades: usize, rotation: f64 },
+    Mask(ApertureMask),
+}
+
+impl Aperture {
+    pub fn polygon(blades: usize, rotation: f64) -> Aperture {
+        if blades < 3 {
+            panic!("Aperture polygon needs at least 3 blades, not {}", blades);
+        }
+        Aperture::Polygon { blades, rotation }
+    }
+
+    pub fn sample(&self, x: f64, y: f64) -> (f64, f64) {
+        match *self {
+            Aperture::Circle => {
+                let d = samplers::to_unit_disc(x, y);
+                (d.x, d.y)
+            },
+            Aperture::Polygon { blades, rotation } => sample_polygon(blades, rotation, x, y),
+            Aperture::Mask(ref mask) => mask.sample(x, y),
+        }
+    }
+}
+
+// Split the polygon into one triangle per blade around the center, use
+// x to pick a triangle and the rest of x and y to pick a point in it.
+fn sample_polygon(blades: usize, rotation: f64, x: f64, y: f64) -> (f64, f64) {
+    let scaled = x * blades as f64;
+    let i = (scaled as usize).min(blades - 1);
+    let u = scaled - i as f64;
+
+    let angle = |k: usize| rotation.to_radians() + 2.0 * PI * k as f64 / blades as f64;
+    let (a0, a1) = (angle(i), angle(i + 1));
+
+    // Uniform over the triangle (center, vertex i, vertex i + 1).
+    let r = u.sqrt();
+    (r * ((1.0 - y) * a0.cos() + y * a1.cos()),
+     r * ((1.0 - y) * a0.sin() + y * a1.sin()))
+}
+
+// A grayscale image covering [-1..1] x [-1..1] that gives the relative
+// transmission through each part of the aperture. Samples are placed
+// with density proportional to the pixel values.
+#[derive(Clone)]
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    // The cumulative distribution of rows, and of pixels within each
+    // row, both normalized to end at 1.
+    row_cdf: Vec<f64>,
+    column_cdfs: Vec<Vec<f64>>,
+}
+
+impl ApertureMask {
+    // Pixel values are given row by row, starting at the top. Negative
+    // values count as 0, and at least one must be positive.
+    pub fn new(width: usize, height: usize, values: &Vec<f64>) -> ApertureMask {
+        if width == 0 || height == 0 {
+            panic!("Aperture mask is empty ({}x{})", width, height);
+        }
+        if values.len() != width * height {
+            panic!("Aperture mask is {}x{} but has {} values", width, height, values.len());
+        }
+
+        let mut row_cdf = Vec::with_capacity(height);
+        let mut column_cdfs = Vec::with_capacity(height);
+        let mut total = 0.0;
+
+        for row in values.chunks(width) {
+            let mut cdf = Vec::with_capacity(width);
+            let mut row_total = 0.0;
+            for v in row {
+                row_total += v.max(0.0);
+                cdf.push(row_total);
+            }
+            for c in cdf.iter_mut() {
+                *c = if row_total > 0.0 { *c / row_total } else { 1.0 };
+            }
+            column_cdfs.push(cdf);
+
+            total += row_total;
+            row_cdf.push(total);
+        }
+
+        if total <= 0.0 {
+            panic!("Aperture mask is completely black");
+        }
+
+        for c in row_cdf.iter_mut() {
+            *c /= total;
+        }
+
+        ApertureMask { width, height, row_cdf, column_cdfs }
+    }
+
+    // Load a mask from a binary (P5) or ASCII (P2) PGM file.
+    pub fn from_pgm(path: &str) -> io::Result<ApertureMask> {
+        let mut data = vec![];
+        File::open(path)?.read_to_end(&mut data)?;
+
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData,
+                                                 format!("bad PGM file: {}", msg));
+
+        // The header is four whitespace-separated tokens, with comments
+        // running from # to the end of the line.
+        let mut pos = 0;
+        let mut header = vec![];
+        while header.len() < 4 {
+            while pos < data.len() && (data[pos] as char).is_whitespace() {
+                pos += 1;
+            }
+            if pos < data.len() && data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            let start = pos;
+            while pos < data.len() && !(data[pos] as char).is_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid("truncated header"));
+            }
+            header.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+        }
+
+        let parse = |s: &String| s.parse::<usize>().map_err(|_| invalid("bad header value"));
+        let width = parse(&header[1])?;
+        let height = parse(&header[2])?;
+        let max_value = parse(&header[3])? as f64;
+        let count = width * height;
+        if count == 0 {
+            return Err(invalid("empty image"));
+        }
+        if max_value <= 0.0 {
+            return Err(invalid("bad maximum value"));
+        }
+
+        let values: Vec<f64> = match header[0].as_str() {
+            "P5" => {
+                // A single whitespace byte separates the header from the
+                // pixels, which are one byte each, or two bytes big
+                // endian if the maximum value is over 255.
+                let pixels = &data[(pos + 1).min(data.len())..];
+                let bytes = if max_value > 255.0 { 2 } else { 1 };
+                if pixels.len() < count * bytes {
+                    return Err(invalid("truncated pixel data"));
+                }
+                (0..count).map(|i| if bytes == 2 {
+                    ((pixels[2 * i] as usize) << 8 | pixels[2 * i + 1] as usize) as f64
+                } else {
+                    pixels[i] as f64
+                }).collect()
+            },
+            "P2" => {
+                let values: Vec<f64> = String::from_utf8_lossy(&data[pos..])
+                    .split_whitespace()
+                    .take(count)
+                    .map(|v| v.parse::<f64>().map_err(|_| invalid("bad pixel value")))
+                    .collect::<io::Result<Vec<f64>>>()?;
+                if values.len() < count {
+                    return Err(invalid("truncated pixel data"));
+                }
+                values
+            },
+            _ => return Err(invalid("not a P2 or P5 PGM file")),
+        };
+
+        if values.iter().all(|&v| v <= 0.0) {
+            return Err(invalid("image is completely black"));
+        }
+
+        Ok(ApertureMask::new(width, height, &values.iter().map(|v| v / max_value).collect()))
+    }
+
+    fn sample(&self, x: f64, y: f64) -> (f64, f64) {
+        let (row, ry) = sample_cdf(&self.row_cdf, y);
+        let (col, rx) = sample_cdf(&self.column_cdfs[row], x);
+
+        // Rows start at the top of the image but y points up.
+        (2.0 * (col as f64 + rx) / self.width as f64 - 1.0,
+         1.0 - 2.0 * (row as f64 + ry) / self.height as f64)
+    }
+}
+
+// Find the bucket of a normalized cumulative distribution that u falls
+// into, and how far through the bucket it is.
+fn sample_cdf(cdf: &Vec<f64>, u: f64) -> (usize, f64) {
+    // The first bucket whose cumulative value is past u; this never
+    // picks an empty bucket.
+    let (mut lo, mut hi) = (0, cdf.len() - 1);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if cdf[mid] > u {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    let i = lo;
+
+    let start = if i == 0 { 0.0 } else { cdf[i - 1] };
+    let width = cdf[i] - start;
+    let offset = if width > 0.0 { ((u - start) / width).min(1.0) } else { 0.5 };
+    (i, offset)
+}
@@ -1,13 +1,24 @@
 
 extern crate clap;
-use self::clap::{Arg, App};
+use self::clap::{Arg, ArgGroup, App, Error, ErrorKind};
 
-use types::Config;
-use constants::*;
+extern crate rand;
+
+use rebound::types::Config;
+use rebound::tiles::{Tile, TileOrder};
+use rebound::animation;
+use rebound::animation::{Animation, Motion};
+use rebound::scenes;
+use rebound::constants::*;
 
 pub fn config_from_args() -> Config {
     let default_sample_root = DEFAULT_SAMPLE_ROOT.to_string();
     let default_max_depth = DEFAULT_MAX_DEPTH.to_string();
+    let default_tile_size = DEFAULT_TILE_SIZE.to_string();
+    let default_checkpoint_interval = DEFAULT_CHECKPOINT_INTERVAL.to_string();
+    let default_worker_timeout = DEFAULT_WORKER_TIMEOUT.to_string();
+    let tile_order_names: Vec<&str> = TileOrder::all().iter().map(|o| o.name()).collect();
+    let scene_names = scenes::scene_names();
 
     let app = App::new("rebound")
         .version("0.1")
@@ -22,6 +33,21 @@ pub fn config_from_args() -> Config {
              .value_name("ROOT")
              .help("Sample root")
              .default_value(default_sample_root.as_str())
+             .validator(positive)
+             .takes_value(true))
+        .arg(Arg::with_name("passes")
+             .short("p")
+             .long("passes")
+             .value_name("PASSES")
+             .help("Render progressively, one sample per pixel per pass, writing the output after every pass")
+             .validator(positive)
+             .takes_value(true))
+        .arg(Arg::with_name("seed")
+             .short("s")
+             .long("seed")
+             .value_name("SEED")
+             .help("Seed for all random sampling; renders with the same seed and settings are identical (default: random)")
+             .validator(number)
              .takes_value(true))
         .arg(Arg::with_name("depth")
              .short("d")
@@ -29,13 +55,15 @@ pub fn config_from_args() -> Config {
              .value_name("DEPTH")
              .help("Maximum recursion depth")
              .default_value(default_max_depth.as_str())
+             .validator(number)
              .takes_value(true))
         .arg(Arg::with_name("scene-name")
              .short("n")
              .long("scene-name")
              .value_name("NAME")
              .help("Scene name")
-             .required(true)
+             .required_unless("resume")
+             .possible_values(&scene_names)
              .takes_value(true))
         .arg(Arg::with_name("output-file")
              .short("o")
@@ -43,15 +71,168 @@ pub fn config_from_args() -> Config {
              .value_name("FILENAME")
              .help("Output filename path")
              .default_value(DEFAULT_OUTPUT_FILENAME)
-             .takes_value(true));
+             .takes_value(true))
+        .arg(Arg::with_name("stats-file")
+             .long("stats-file")
+             .value_name("FILENAME")
+             .help("Write render statistics as JSON to this path")
+             .takes_value(true))
+        .arg(Arg::with_name("tile-size")
+             .short("t")
+             .long("tile-size")
+             .value_name("PIXELS")
+             .help("Width and height of render tiles")
+             .default_value(default_tile_size.as_str())
+             .validator(positive)
+             .takes_value(true))
+        .arg(Arg::with_name("tile-order")
+             .long("tile-order")
+             .value_name("ORDER")
+             .help("Order in which tiles are rendered")
+             .possible_values(&tile_order_names)
+             .default_value(DEFAULT_TILE_ORDER)
+             .takes_value(true))
+        .arg(Arg::with_name("checkpoint")
+             .long("checkpoint")
+             .value_name("FILENAME")
+             .help("Periodically save the render state to this path")
+             .takes_value(true))
+        .arg(Arg::with_name("checkpoint-interval")
+             .long("checkpoint-interval")
+             .value_name("SECONDS")
+             .help("Time between checkpoint saves")
+             .default_value(default_checkpoint_interval.as_str())
+             .validator(number)
+             .takes_value(true))
+        .arg(Arg::with_name("resume")
+             .long("resume")
+             .value_name("FILENAME")
+             .help("Resume the render saved in this checkpoint; its scene and render settings override the ones given here")
+             .takes_value(true))
+        .arg(Arg::with_name("workers")
+             .long("workers")
+             .value_name("ADDR[,ADDR...]")
+             .help("Render on rebound-worker processes at these host:port addresses instead of locally")
+             .takes_value(true))
+        .arg(Arg::with_name("worker-timeout")
+             .long("worker-timeout")
+             .value_name("SECONDS")
+             .help("Time to wait for a worker to connect or reply before giving its tiles to the other workers")
+             .default_value(default_worker_timeout.as_str())
+             .validator(positive)
+             .takes_value(true))
+        .arg(Arg::with_name("region")
+             .long("region")
+             .value_name("X0,Y0,X1,Y1")
+             .help("Only render pixels from (X0, Y0) up to but not including (X1, Y1); the rest of the image is black")
+             .validator(region)
+             .takes_value(true))
+        .arg(Arg::with_name("crop")
+             .long("crop")
+             .requires("region")
+             .help("Write only the rendered region to the output file"))
+        .arg(Arg::with_name("frames")
+             .long("frames")
+             .value_name("N")
+             .help("Number of animation frames to render, written to numbered output files")
+             .requires("motion")
+             .validator(positive)
+             .takes_value(true))
+        .arg(Arg::with_name("turntable")
+             .long("turntable")
+             .requires("frames")
+             .conflicts_with_all(&["keyframes", "checkpoint", "resume"])
+             .help("Orbit the camera once around its look-at point over the animation"))
+        .arg(Arg::with_name("keyframes")
+             .long("keyframes")
+             .value_name("FILENAME")
+             .conflicts_with_all(&["checkpoint", "resume"])
+             .help("Move the camera between keyframes read from this file, one per line: frame, eye, look-at and up (10 numbers); --frames defaults to the last keyframe + 1")
+             .takes_value(true))
+        .arg(Arg::with_name("camera")
+             .long("camera")
+             .value_name("NAME")
+             .help("Render with this named camera of the scene instead of its default camera")
+             .takes_value(true))
+        .arg(Arg::with_name("models")
+             .long("models")
+             .value_name("DIR")
+             .help("Directory to load the scenes' model files from")
+             .default_value(DEFAULT_MODELS_DIR)
+             .takes_value(true))
+        .arg(Arg::with_name("all-cameras")
+             .long("all-cameras")
+             .conflicts_with_all(&["camera", "checkpoint", "resume"])
+             .help("Render every camera of the scene, each to output files named after it"))
+        .group(ArgGroup::with_name("motion")
+               .args(&["turntable", "keyframes"]));
 
     let ms = app.get_matches();
 
+    let frames = ms.value_of("frames").map(|f| f.parse::<usize>().unwrap());
+    let animation = if ms.occurrences_of("turntable") > 0 {
+        Some(Animation { frames: frames.unwrap(), motion: Motion::Turntable })
+    } else if let Some(path) = ms.value_of("keyframes") {
+        let keys = animation::load_keyframes(path).expect("Could not load keyframes");
+        Some(Animation {
+            frames: frames.unwrap_or(keys[keys.len() - 1].frame + 1),
+            motion: Motion::Keyframes(keys),
+        })
+    } else {
+        None
+    };
+
     Config {
         quiet: ms.occurrences_of("quiet") > 0,
         sample_root: ms.value_of("sample-root").unwrap().parse().unwrap(),
+        passes: ms.value_of("passes").map(|p| p.parse().unwrap()),
         max_depth: ms.value_of("depth").unwrap().parse().unwrap(),
         output_file: String::from(ms.value_of("output-file").unwrap()),
-        scene_name: String::from(ms.value_of("scene-name").unwrap()),
+        scene_name: String::from(ms.value_of("scene-name").unwrap_or("")),
+        stats_file: ms.value_of("stats-file").map(String::from),
+        tile_size: ms.value_of("tile-size").unwrap().parse().unwrap(),
+        tile_order: TileOrder::from_name(ms.value_of("tile-order").unwrap()).unwrap(),
+        seed: ms.value_of("seed").map(|s| s.parse().unwrap()).unwrap_or_else(rand::random),
+        checkpoint_file: ms.value_of("checkpoint").map(String::from),
+        checkpoint_interval: ms.value_of("checkpoint-interval").unwrap().parse().unwrap(),
+        resume_file: ms.value_of("resume").map(String::from),
+        workers: ms.value_of("workers")
+            .map(|w| w.split(',').map(String::from).collect())
+            .unwrap_or_default(),
+        worker_timeout: ms.value_of("worker-timeout").unwrap().parse().unwrap(),
+        region: ms.value_of("region").map(|r| Tile::from_corners(r).unwrap()),
+        crop: ms.occurrences_of("crop") > 0,
+        animation,
+        camera_name: ms.value_of("camera").map(String::from),
+        all_cameras: ms.occurrences_of("all-cameras") > 0,
+        models_dir: String::from(ms.value_of("models").unwrap()),
+    }
+}
+
+// Validators for numeric arguments, so that bad values are reported by
+// clap and the parses in config_from_args can't fail.
+fn number(v: String) -> Result<(), String> {
+    v.parse::<u64>().map(|_| ()).map_err(|_| format!("expected a whole number, got {}", v))
+}
+
+fn positive(v: String) -> Result<(), String> {
+    match v.parse::<usize>() {
+        Ok(n) if n >= 1 => Ok(()),
+        _ => Err(format!("expected a whole number of at least 1, got {}", v)),
+    }
+}
+
+// Whether the region fits the image depends on the scene, so main
+// checks that.
+fn region(v: String) -> Result<(), String> {
+    match Tile::from_corners(&v) {
+        Some(r) if r.x0 < r.x1 && r.y0 < r.y1 => Ok(()),
+        _ => Err(format!("expected X0,Y0,X1,Y1 with X0 < X1 and Y0 < Y1, got {}", v)),
     }
 }
+
+// Report a problem with the arguments that can only be found once the
+// scene is known, the way clap reports its own, and exit.
+pub fn usage_error(message: &str) -> ! {
+    Error::with_description(message, ErrorKind::InvalidValue).exit()
+}
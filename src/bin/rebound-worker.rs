@@ -0,0 +1,33 @@
+
+extern crate clap;
+use clap::{Arg, App};
+
+extern crate rebound;
+use rebound::network;
+use rebound::constants::{DEFAULT_MODELS_DIR, DEFAULT_WORKER_ADDRESS};
+
+// Render tiles on behalf of a rebound coordinator; see the --workers
+// option of the main binary.
+fn main() {
+    let ms = App::new("rebound-worker")
+        .version("0.1")
+        .author("Jonathan Daugherty")
+        .arg(Arg::with_name("listen")
+             .short("l")
+             .long("listen")
+             .value_name("ADDR")
+             .help("Address to accept coordinator connections on")
+             .default_value(DEFAULT_WORKER_ADDRESS)
+             .takes_value(true))
+        .arg(Arg::with_name("models")
+             .long("models")
+             .value_name("DIR")
+             .help("Directory to load the scenes' model files from")
+             .default_value(DEFAULT_MODELS_DIR)
+             .takes_value(true))
+        .get_matches();
+
+    let addr = ms.value_of("listen").unwrap();
+    println!("Listening on {}", addr);
+    network::serve(addr, ms.value_of("models").unwrap()).expect("Worker failed");
+}
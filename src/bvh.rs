@@ -0,0 +1,469 @@
+
+extern crate nalgebra;
+
+use nalgebra::{Vector3};
+
+use types::*;
+
+// An axis-aligned bounding box.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+pub struct Aabb {
+    pub min: Vector3<f64>,
+    pub max: Vector3<f64>,
+}
+
+impl Aabb {
+    pub fn new(min: Vector3<f64>, max: Vector3<f64>) -> Aabb {
+        Aabb { min, max }
+    }
+
+    // A box containing nothing, which any union replaces.
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vector3::repeat(::std::f64::INFINITY),
+            max: Vector3::repeat(::std::f64::NEG_INFINITY),
+        }
+    }
+
+    // The smallest box containing all of the points.
+    pub fn around(points: &[Vector3<f64>]) -> Aabb {
+        points.iter().fold(Aabb::empty(), |b, p| b.union(&Aabb::new(*p, *p)))
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.zip_map(&other.min, f64::min),
+            max: self.max.zip_map(&other.max, f64::max),
+        }
+    }
+
+    pub fn centroid(&self) -> Vector3<f64> {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        if d.x < 0.0 {
+            0.0
+        } else {
+            2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+        }
+    }
+
+    // Whether the ray passes through the box at a distance before
+    // t_max, by intersecting the slabs between each pair of faces.
+    // inv_direction is the reciprocal of the ray direction.
+    pub fn hit(&self, r: &Ray, inv_direction: &Vector3<f64>, t_max: f64) -> bool {
+        let mut t0 = 0.0;
+        let mut t1 = t_max;
+        for axis in 0..3 {
+            // Parallel to the slab: the ray is either always between its
+            // faces or never. Checking this directly avoids 0 * inf = NaN
+            // for rays lying in a face.
+            if inv_direction[axis].is_infinite() {
+                if r.origin[axis] < self.min[axis] || r.origin[axis] > self.max[axis] {
+                    return false;
+                }
+                continue;
+            }
+            let near = (self.min[axis] - r.origin[axis]) * inv_direction[axis];
+            let far = (self.max[axis] - r.origin[axis]) * inv_direction[axis];
+            t0 = near.min(far).max(t0);
+            t1 = near.max(far).min(t1);
+            if t0 > t1 {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Relative costs of visiting a node and intersecting an item, for the
+// surface area heuristic.
+const TRAVERSAL_COST: f64 = 1.0;
+const INTERSECTION_COST: f64 = 1.0;
+// Candidate split planes per axis.
+const SAH_BUCKETS: usize = 12;
+// Nodes with more items than this are always split.
+const MAX_LEAF_SIZE: usize = 8;
+// Below this depth nodes are split in the middle of their item list
+// instead of by cost, which halves them, so that the tree stays
+// shallow enough for the fixed size traversal stack: a tree of depth d
+// needs at most d + 1 stack entries.
+const MAX_SAH_DEPTH: usize = 32;
+const STACK_SIZE: usize = 64;
+
+enum Node {
+    // Items order[start..start + count].
+    Leaf { bounds: Aabb, start: usize, count: usize },
+    // The first child immediately follows its parent.
+    Interior { bounds: Aabb, second: usize, axis: usize },
+}
+
+// A bounding volume hierarchy over a list of items, built with the
+// surface area heuristic. Items without bounds, like infinite planes,
+// are kept outside the tree and tested for every ray. Items are only
+// referred to by their index, so the same hierarchy works for scene
+// objects and for the triangles of a mesh.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    order: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn build(bounds: &[Option<Aabb>]) -> Bvh {
+        Bvh::build_from_depth(bounds, 0)
+    }
+
+    // Build as if the root were at the given depth, so tests can build
+    // nodes past MAX_SAH_DEPTH without a pathological scene.
+    fn build_from_depth(bounds: &[Option<Aabb>], depth: usize) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: vec![],
+            order: vec![],
+            unbounded: vec![],
+        };
+
+        let mut items: Vec<(usize, Aabb, Vector3<f64>)> = vec![];
+        for (i, b) in bounds.iter().enumerate() {
+            match *b {
+                Some(b) => items.push((i, b, b.centroid())),
+                None => bvh.unbounded.push(i),
+            }
+        }
+
+        if !items.is_empty() {
+            bvh.build_node(&mut items[..], depth);
+        }
+        bvh
+    }
+
+    fn build_node(&mut self, items: &mut [(usize, Aabb, Vector3<f64>)], depth: usize) {
+        let bounds = items.iter().fold(Aabb::empty(), |b, i| b.union(&i.1));
+        let centroids = items.iter().fold(Aabb::empty(), |b, i| b.union(&Aabb::new(i.2, i.2)));
+
+        if items.len() > 1 {
+            let split = if depth < MAX_SAH_DEPTH {
+                split(items, &bounds, &centroids)
+            } else if items.len() > MAX_LEAF_SIZE {
+                Some((widest_axis(&centroids), items.len() / 2))
+            } else {
+                None
+            };
+
+            if let Some((axis, mid)) = split {
+                let node = self.nodes.len();
+                self.nodes.push(Node::Interior { bounds, second: 0, axis });
+
+                let (first, second) = items.split_at_mut(mid);
+                self.build_node(first, depth + 1);
+                let second_index = self.nodes.len();
+                self.build_node(second, depth + 1);
+
+                if let Node::Interior { second: ref mut s, .. } = self.nodes[node] {
+                    *s = second_index;
+                }
+                return;
+            }
+        }
+
+        self.nodes.push(Node::Leaf { bounds, start: self.order.len(), count: items.len() });
+        self.order.extend(items.iter().map(|i| i.0));
+    }
+
+    // Find the closest hit. hit_item tests one item for a hit closer
+    // than the given distance, returning the hit's distance and value.
+    pub fn hit<T, F>(&self, r: &Ray, mut hit_item: F) -> Option<T>
+        where F: FnMut(usize, f64) -> Option<(f64, T)> {
+        let mut closest: Option<(f64, T)> = None;
+        let t_max = |c: &Option<(f64, T)>| c.as_ref().map_or(::std::f64::INFINITY, |c| c.0);
+
+        for &i in self.unbounded.iter() {
+            if let Some(h) = hit_item(i, t_max(&closest)) {
+                closest = Some(h);
+            }
+        }
+
+        if self.nodes.is_empty() {
+            return closest.map(|c| c.1);
+        }
+
+        let inv_direction = r.direction.map(|d| 1.0 / d);
+        let mut stack = [0; STACK_SIZE];
+        let mut stack_len = 1;
+        while stack_len > 0 {
+            stack_len -= 1;
+            let n = stack[stack_len];
+            match self.nodes[n] {
+                Node::Leaf { ref bounds, start, count } => {
+                    if !bounds.hit(r, &inv_direction, t_max(&closest)) {
+                        continue;
+                    }
+                    for &i in self.order[start..start + count].iter() {
+                        if let Some(h) = hit_item(i, t_max(&closest)) {
+                            closest = Some(h);
+                        }
+                    }
+                },
+                Node::Interior { ref bounds, second, axis } => {
+                    if !bounds.hit(r, &inv_direction, t_max(&closest)) {
+                        continue;
+                    }
+                    // Visit the child nearer the ray origin first, so
+                    // that its hits can rule out the other child.
+                    let (near, far) = if r.direction[axis] < 0.0 {
+                        (second, n + 1)
+                    } else {
+                        (n + 1, second)
+                    };
+                    stack[stack_len] = far;
+                    stack[stack_len + 1] = near;
+                    stack_len += 2;
+                },
+            }
+        }
+
+        closest.map(|c| c.1)
+    }
+}
+
+// Choose where to split items, by bucketing their centroids along the
+// axis they are most spread out on and picking the bucket boundary with
+// the lowest surface area heuristic cost. Items are partitioned so that
+// the first mid belong to the first child. Returns None if a leaf is
+// cheaper.
+fn split(items: &mut [(usize, Aabb, Vector3<f64>)], bounds: &Aabb,
+         centroids: &Aabb) -> Option<(usize, usize)> {
+    let extent = centroids.max - centroids.min;
+    let axis = widest_axis(centroids);
+
+    // All centroids coincide, so no plane separates them; split in the
+    // middle of the list if the node is too big for a leaf.
+    if extent[axis] <= 0.0 {
+        return if items.len() > MAX_LEAF_SIZE { Some((axis, items.len() / 2)) } else { None };
+    }
+
+    let bucket = |c: &Vector3<f64>| {
+        let b = ((c[axis] - centroids.min[axis]) / extent[axis] * SAH_BUCKETS as f64) as usize;
+        b.min(SAH_BUCKETS - 1)
+    };
+
+    let mut counts = [0usize; SAH_BUCKETS];
+    let mut boxes = [Aabb::empty(); SAH_BUCKETS];
+    for i in items.iter() {
+        let b = bucket(&i.2);
+        counts[b] += 1;
+        boxes[b] = boxes[b].union(&i.1);
+    }
+
+    // The cost of splitting after each bucket but the last.
+    let mut best: Option<(usize, f64)> = None;
+    for s in 0..(SAH_BUCKETS - 1) {
+        let (mut below, mut above) = (Aabb::empty(), Aabb::empty());
+        let (mut n_below, mut n_above) = (0, 0);
+        for b in 0..(s + 1) {
+            below = below.union(&boxes[b]);
+            n_below += counts[b];
+        }
+        for b in (s + 1)..SAH_BUCKETS {
+            above = above.union(&boxes[b]);
+            n_above += counts[b];
+        }
+
+        let cost = TRAVERSAL_COST + INTERSECTION_COST *
+            (n_below as f64 * below.surface_area() + n_above as f64 * above.surface_area()) /
+            bounds.surface_area().max(::std::f64::MIN_POSITIVE);
+        if n_below > 0 && n_above > 0 && best.map_or(true, |(_, c)| cost < c) {
+            best = Some((s, cost));
+        }
+    }
+
+    let (s, cost) = best?;
+    let leaf_cost = INTERSECTION_COST * items.len() as f64;
+    if cost >= leaf_cost && items.len() <= MAX_LEAF_SIZE {
+        return None;
+    }
+
+    // Partition the items in place around the chosen boundary.
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if bucket(&items[i].2) <= s {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    Some((axis, mid))
+}
+
+fn widest_axis(b: &Aabb) -> usize {
+    let extent = b.max - b.min;
+    if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use samplers;
+
+    fn ray(origin: Vector3<f64>, direction: Vector3<f64>) -> Ray {
+        Ray { origin, direction: direction.normalize(), time: 0.0 }
+    }
+
+    fn unit_box(center: Vector3<f64>) -> Aabb {
+        Aabb::new(center - Vector3::repeat(0.5), center + Vector3::repeat(0.5))
+    }
+
+    // The distance at which the ray enters b, or zero if it starts
+    // inside it. This computes the slabs as Aabb::hit does, so that the
+    // two agree on rays grazing a box.
+    fn enter(b: &Aabb, r: &Ray) -> Option<f64> {
+        let inv_direction = r.direction.map(|d| 1.0 / d);
+        let (mut t0, mut t1) = (0.0, ::std::f64::INFINITY);
+        for axis in 0..3 {
+            let near = (b.min[axis] - r.origin[axis]) * inv_direction[axis];
+            let far = (b.max[axis] - r.origin[axis]) * inv_direction[axis];
+            t0 = near.min(far).max(t0);
+            t1 = near.max(far).min(t1);
+        }
+        if t0 <= t1 { Some(t0) } else { None }
+    }
+
+    // Boxes and, for items without bounds, planes of constant y.
+    fn item_hit(items: &[(Option<Aabb>, f64)], i: usize, r: &Ray) -> Option<f64> {
+        match items[i].0 {
+            Some(ref b) => enter(b, r),
+            None => Some((items[i].1 - r.origin.y) / r.direction.y).filter(|&t| t > 0.0),
+        }
+    }
+
+    // Check the hierarchy's closest hits against testing every item,
+    // for rays from around the items towards random ones.
+    fn check_against_brute_force(bvh: &Bvh, items: &[(Option<Aabb>, f64)]) {
+        let mut rng = samplers::seeded(1).rng;
+        let mut hits = 0;
+        let rays = 2000;
+
+        for _ in 0..rays {
+            let target = match items[rng.gen_range(0, items.len())].0 {
+                Some(b) => b.centroid(),
+                None => Vector3::zeros(),
+            };
+            let mut v = || Vector3::new(rng.gen::<f64>() - 0.5, rng.gen::<f64>() - 0.5,
+                                        rng.gen::<f64>() - 0.5);
+            let origin = v() * 80.0;
+            let r = ray(origin, target - origin + v());
+
+            let expected = (0..items.len())
+                .filter_map(|i| item_hit(items, i, &r))
+                .fold(None, |c: Option<f64>, t| Some(c.map_or(t, |c| c.min(t))));
+            let found = bvh.hit(&r, |i, t_max| {
+                item_hit(items, i, &r).filter(|&t| t < t_max).map(|t| (t, t))
+            });
+
+            assert_eq!(found, expected);
+            if found.is_some() {
+                hits += 1;
+            }
+        }
+        assert!(hits > rays / 2, "only {} of {} rays hit", hits, rays);
+    }
+
+    fn depth(bvh: &Bvh, n: usize) -> usize {
+        match bvh.nodes[n] {
+            Node::Leaf { .. } => 1,
+            Node::Interior { second, .. } => 1 + depth(bvh, n + 1).max(depth(bvh, second)),
+        }
+    }
+
+    #[test]
+    fn aabb_hit() {
+        let b = unit_box(Vector3::new(0.0, 0.0, -5.0));
+        let hit = |r: &Ray, t_max: f64| b.hit(r, &r.direction.map(|d| 1.0 / d), t_max);
+        let inf = ::std::f64::INFINITY;
+
+        assert!(hit(&ray(Vector3::zeros(), Vector3::new(0.0, 0.0, -1.0)), inf));
+        assert!(hit(&ray(Vector3::zeros(), Vector3::new(0.09, 0.09, -1.0)), inf));
+        assert!(!hit(&ray(Vector3::zeros(), Vector3::new(0.0, 0.0, 1.0)), inf));
+        assert!(!hit(&ray(Vector3::zeros(), Vector3::new(0.2, 0.0, -1.0)), inf));
+        assert!(!hit(&ray(Vector3::zeros(), Vector3::new(0.0, 0.0, -1.0)), 4.0));
+        assert!(hit(&ray(Vector3::new(0.0, 0.0, -5.0), Vector3::new(1.0, 0.0, 0.0)), inf));
+        assert!(!hit(&ray(Vector3::new(0.0, 0.7, 0.0), Vector3::new(0.0, 0.0, -1.0)), inf));
+        // Along a face or an edge, parallel to the other axes.
+        assert!(hit(&ray(Vector3::new(0.5, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0)), inf));
+        assert!(hit(&ray(Vector3::new(-0.5, 0.5, 0.0), Vector3::new(0.0, 0.0, -1.0)), inf));
+    }
+
+    // Boxes scattered through a cube 30 units wide, and planes above
+    // and below them.
+    fn scattered_items() -> Vec<(Option<Aabb>, f64)> {
+        let mut rng = samplers::seeded(2).rng;
+        let mut items: Vec<(Option<Aabb>, f64)> = (0..500).map(|_| {
+            let mut v = || Vector3::new(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>());
+            let center = (v() - Vector3::repeat(0.5)) * 30.0;
+            let size = v();
+            (Some(Aabb::new(center - size, center + size)), 0.0)
+        }).collect();
+        items.push((None, -20.0));
+        items.push((None, 25.0));
+        items
+    }
+
+    fn bounds(items: &[(Option<Aabb>, f64)]) -> Vec<Option<Aabb>> {
+        items.iter().map(|i| i.0).collect()
+    }
+
+    #[test]
+    fn scattered_boxes_and_planes() {
+        let items = scattered_items();
+        let bvh = Bvh::build(&bounds(&items));
+        assert_eq!(bvh.unbounded, vec![500, 501]);
+        check_against_brute_force(&bvh, &items);
+    }
+
+    #[test]
+    fn past_max_sah_depth() {
+        // Only the top two levels are split by cost; below them nodes
+        // are split in the middle of their item list.
+        let items = scattered_items();
+        let bvh = Bvh::build_from_depth(&bounds(&items), MAX_SAH_DEPTH - 2);
+        assert!(depth(&bvh, 0) > 2);
+        assert!(MAX_SAH_DEPTH - 2 + depth(&bvh, 0) < STACK_SIZE);
+        check_against_brute_force(&bvh, &items);
+    }
+
+    #[test]
+    fn equal_centroids() {
+        let b = unit_box(Vector3::new(0.0, 0.0, -5.0));
+        let bounds = vec![Some(b); MAX_LEAF_SIZE * 3 + 1];
+        let bvh = Bvh::build(&bounds);
+
+        for n in bvh.nodes.iter() {
+            if let Node::Leaf { count, .. } = *n {
+                assert!(count <= MAX_LEAF_SIZE);
+            }
+        }
+        let mut order = bvh.order.clone();
+        order.sort();
+        assert_eq!(order, (0..bounds.len()).collect::<Vec<usize>>());
+
+        let r = ray(Vector3::zeros(), Vector3::new(0.0, 0.0, -1.0));
+        let mut tested = vec![];
+        let found = bvh.hit(&r, |i, _| {
+            tested.push(i);
+            enter(&b, &r).map(|t| (t, i))
+        });
+        assert!(found.is_some());
+        tested.sort();
+        assert_eq!(tested, order);
+    }
+}
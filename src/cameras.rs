@@ -2,17 +2,23 @@
 extern crate nalgebra;
 use nalgebra::{Vector3};
 
-extern crate rand;
-use self::rand::Rng;
+use std::f64::consts::PI;
 
-extern crate samplers;
 use types::*;
+use constants::*;
+use aperture::Aperture;
 
-use std::io::stdout;
-use std::io::Write;
+// The view plane distance that gives a vertical field of view of fov
+// degrees across the view plane, at a zoom factor of 1.
+pub fn vp_distance_for_fov(vp: &ViewPlane, fov: f64) -> f64 {
+    0.5 * vp.vres as f64 * vp.pixel_size / (0.5 * fov.to_radians()).tan()
+}
 
-extern crate rayon;
-use rayon::prelude::*;
+// The vertical field of view, in degrees, of a lens with the given
+// focal length on a sensor of the given height, both in millimeters.
+pub fn fov_for_lens(focal_length: f64, sensor_height: f64) -> f64 {
+    2.0 * (0.5 * sensor_height / focal_length).atan().to_degrees()
+}
 
 pub struct PinholeCamera {
     pub core: CameraCore,
@@ -21,73 +27,104 @@ pub struct PinholeCamera {
 }
 
 impl PinholeCamera {
+    // A camera with a vertical field of view of fov degrees across the
+    // given view plane.
+    pub fn with_fov(core: CameraCore, vp: &ViewPlane, fov: f64) -> PinholeCamera {
+        PinholeCamera {
+            core,
+            vp_distance: vp_distance_for_fov(vp, fov),
+            zoom_factor: 1.0,
+        }
+    }
+
+    // A camera framing the view plane like a lens with the given focal
+    // length on a sensor of the given height, both in millimeters.
+    pub fn with_focal_length(core: CameraCore, vp: &ViewPlane, focal_length: f64,
+                             sensor_height: f64) -> PinholeCamera {
+        PinholeCamera::with_fov(core, vp, fov_for_lens(focal_length, sensor_height))
+    }
+
     fn ray_direction(&self, x: f64, y: f64) -> Vector3<f64> {
         (x * self.core.u + y * self.core.v - self.vp_distance * self.core.w).normalize()
     }
 }
 
 impl Camera for PinholeCamera {
-    fn render(&self, scene: &Scene) -> Image {
-        let mut img = Image::new(scene.view_plane.hres, scene.view_plane.vres);
-        let mut sampler = samplers::new();
-
-        let pixel_sample_sets: Vec<Vec<samplers::UnitSquareSample>> =
-            if scene.config.sample_root == 1 {
-                vec!(samplers::u_grid_regular(scene.config.sample_root))
-            } else {
-                (0..img.width).map(|_|
-                    samplers::u_grid_jittered(&mut sampler, scene.config.sample_root)).collect()
-            };
+    fn core(&self) -> &CameraCore {
+        &self.core
+    }
+
+    fn set_core(&mut self, core: CameraCore) {
+        self.core = core;
+    }
 
-        let hemi_sample_sets: Vec<Vec<Vec<Vector3<f64>>>> =
-            (0..img.width).map(|_|
-                (0..scene.config.max_depth).map(|_|
-                    samplers::to_hemisphere(
-                        samplers::u_grid_jittered(&mut sampler, scene.config.sample_root),
-                        0.0)
-                    ).collect()
-                ).collect();
-
-        let total_pixels = (img.height * img.width) as f64;
-        let half_img_h = img.height as f64 * 0.5;
-        let half_img_w = img.width as f64 * 0.5;
-        let mut sample_set_indexes: Vec<usize> = (0..img.width).collect();
-        let pixel_denom = 1.0 / ((scene.config.sample_root * scene.config.sample_root) as f64);
-        let adjusted_pixel_size = scene.view_plane.pixel_size / self.zoom_factor;
-
-        for row in 0..img.height {
-            sampler.rng.shuffle(&mut sample_set_indexes);
-
-            let row_pixels = (0..img.width).map(|col| {
-                let mut color = black();
-                let pixel_samples = &pixel_sample_sets[sample_set_indexes[col] % pixel_sample_sets.len()];
-
-                for (index, point) in pixel_samples.iter().enumerate() {
-                    let u = adjusted_pixel_size * (col as f64 - half_img_w + point.x);
-                    let v = adjusted_pixel_size * ((img.height - row) as f64 - half_img_h + point.y);
-                    let r = Ray {
-                        direction: self.ray_direction(u, v),
-                        origin: self.core.eye,
-                    };
-
-                    color += scene.color(&r, index, &hemi_sample_sets[sample_set_indexes[col]], 0);
-                }
-
-                color *= pixel_denom;
-                color.max_to_one();
-                color
-            }).collect();
-
-            img.set_row(row, row_pixels);
-
-            let progress = 100.0 * (((row + 1) * img.width) as f64) / total_pixels;
-            print!("  {} %\r", progress as u32);
-            stdout().flush().unwrap();
+    fn ray(&self, vp: &ViewPlane, s: &CameraSample) -> Option<Ray> {
+        let adjusted_pixel_size = vp.pixel_size / self.zoom_factor;
+
+        Some(Ray {
+            direction: self.ray_direction(adjusted_pixel_size * s.px,
+                                          adjusted_pixel_size * s.py),
+            origin: self.core.eye,
+            time: self.core.shutter_time(s.t),
+        })
+    }
+}
+
+// A camera with parallel projection: every ray travels along -w,
+// starting from the point on the view plane that it samples. The view
+// plane is centered on the eye, so its size in world units is set by
+// the view plane's pixel size and the zoom factor.
+pub struct OrthographicCamera {
+    pub core: CameraCore,
+    pub zoom_factor: f64,
+}
+
+impl OrthographicCamera {
+    // A camera whose view is height world units tall.
+    pub fn with_height(core: CameraCore, vp: &ViewPlane, height: f64) -> OrthographicCamera {
+        OrthographicCamera {
+            core,
+            zoom_factor: vp.vres as f64 * vp.pixel_size / height,
         }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn core(&self) -> &CameraCore {
+        &self.core
+    }
+
+    fn set_core(&mut self, core: CameraCore) {
+        self.core = core;
+    }
+
+    fn ray(&self, vp: &ViewPlane, s: &CameraSample) -> Option<Ray> {
+        let adjusted_pixel_size = vp.pixel_size / self.zoom_factor;
+
+        Some(Ray {
+            direction: -self.core.w,
+            origin: self.core.eye +
+                adjusted_pixel_size * s.px * self.core.u +
+                adjusted_pixel_size * s.py * self.core.v,
+            time: self.core.shutter_time(s.t),
+        })
+    }
+}
 
-        println!("");
+// Where an autofocusing camera measures the focus distance.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum FocusPoint {
+    Center,
+    // Image pixel coordinates: column, then row from the top.
+    Pixel(usize, usize),
+}
 
-        img
+impl Default for FocusPoint {
+    fn default() -> FocusPoint {
+        FocusPoint::Center
     }
 }
 
@@ -97,9 +134,55 @@ pub struct ThinLensCamera {
     pub zoom_factor: f64,
     pub lens_radius: f64,
     pub focal_plane_distance: f64,
+    pub aperture: Aperture,
+    // If set, Scene::autofocus replaces focal_plane_distance with the
+    // distance to whatever is visible at this point.
+    pub autofocus: Option<FocusPoint>,
 }
 
 impl ThinLensCamera {
+    // A camera with a vertical field of view of fov degrees across the
+    // given view plane.
+    pub fn with_fov(core: CameraCore, vp: &ViewPlane, fov: f64, lens_radius: f64,
+                    focal_plane_distance: f64) -> ThinLensCamera {
+        ThinLensCamera {
+            core,
+            vp_distance: vp_distance_for_fov(vp, fov),
+            zoom_factor: 1.0,
+            lens_radius,
+            focal_plane_distance,
+            aperture: Aperture::Circle,
+            autofocus: None,
+        }
+    }
+
+    pub fn with_aperture(mut self, aperture: Aperture) -> ThinLensCamera {
+        self.aperture = aperture;
+        self
+    }
+
+    // Focus on whatever is at the center of the image.
+    pub fn with_autofocus(self) -> ThinLensCamera {
+        self.with_focus_point(FocusPoint::default())
+    }
+
+    pub fn with_focus_point(mut self, point: FocusPoint) -> ThinLensCamera {
+        self.autofocus = Some(point);
+        self
+    }
+
+    // A camera with the framing and depth of field of a lens with the
+    // given focal length and f-stop on a sensor of the given height.
+    // Lengths are in millimeters, except for the focus distance, which
+    // is in world units; see MILLIMETERS_PER_UNIT.
+    pub fn with_lens(core: CameraCore, vp: &ViewPlane, focal_length: f64, sensor_height: f64,
+                     f_stop: f64, focus_distance: f64) -> ThinLensCamera {
+        let aperture_diameter = focal_length / f_stop;
+        ThinLensCamera::with_fov(core, vp, fov_for_lens(focal_length, sensor_height),
+                                 0.5 * aperture_diameter / MILLIMETERS_PER_UNIT,
+                                 focus_distance)
+    }
+
     fn ray_direction(&self, px: f64, py: f64, lx: f64, ly: f64) -> Vector3<f64> {
         let px2 = px * self.focal_plane_distance / self.vp_distance;
         let py2 = py * self.focal_plane_distance / self.vp_distance;
@@ -107,55 +190,297 @@ impl ThinLensCamera {
             (py2 - ly) * self.core.v -
             self.focal_plane_distance * self.core.w).normalize()
     }
+
+    // The ray for a sample through the point (lpx, lpy) on the lens.
+    fn lens_ray(&self, vp: &ViewPlane, s: &CameraSample, lpx: f64, lpy: f64) -> Ray {
+        let adjusted_pixel_size = vp.pixel_size / self.zoom_factor;
+
+        Ray {
+            direction: self.ray_direction(adjusted_pixel_size * s.px,
+                                          adjusted_pixel_size * s.py,
+                                          lpx, lpy),
+            origin: self.core.eye + lpx * self.core.u + lpy * self.core.v,
+            time: self.core.shutter_time(s.t),
+        }
+    }
 }
 
 impl Camera for ThinLensCamera {
-    fn render(&self, scene: &Scene) -> Image {
-        let mut img = Image::new(scene.view_plane.hres, scene.view_plane.vres);
-        let mut sampler = samplers::new();
-        let samples = MasterSampleSets::new(&mut sampler, scene.config.sample_root, scene.config.max_depth,
-                                            img.width);
-
-        let half_img_h = img.height as f64 * 0.5;
-        let half_img_w = img.width as f64 * 0.5;
-        let pixel_denom = 1.0 / ((scene.config.sample_root * scene.config.sample_root) as f64);
-        let adjusted_pixel_size = scene.view_plane.pixel_size / self.zoom_factor;
-
-        let rows: Vec<usize> = (0..img.height).collect();
-        let row_pixel_vecs: Vec<Vec<Color>> = rows.par_iter().map(|row| {
-            let sample_set_indexes = samples.shuffle_indices();
-
-            let row_pixels = (0..img.width).map(|col| {
-                let mut color = black();
-                let pixel_samples = &samples.pixel_sets[sample_set_indexes[col] % samples.pixel_sets.len()];
-                let disc_samples = &samples.disc_sets[sample_set_indexes[col] % samples.disc_sets.len()];
-
-                for (index, point) in pixel_samples.iter().enumerate() {
-                    let u = adjusted_pixel_size * (col as f64 - half_img_w + point.x);
-                    let v = adjusted_pixel_size * ((img.height - *row) as f64 - half_img_h + point.y);
-                    let lens_sample = &disc_samples[index];
-                    let lpx = lens_sample.x * self.lens_radius;
-                    let lpy = lens_sample.y * self.lens_radius;
-                    let r = Ray {
-                        direction: self.ray_direction(u, v, lpx, lpy),
-                        origin: self.core.eye + lpx * self.core.u + lpy * self.core.v,
-                    };
-
-                    color += scene.color(&r, index, &samples.hemi_sets[sample_set_indexes[col]], 0);
-                }
-
-                color *= pixel_denom;
-                color.max_to_one();
-                color
-            }).collect();
-
-            row_pixels
-        }).collect();
-
-        for (row, v) in row_pixel_vecs.iter().enumerate() {
-            img.set_row(row, v.clone());
+    fn core(&self) -> &CameraCore {
+        &self.core
+    }
+
+    fn set_core(&mut self, core: CameraCore) {
+        self.core = core;
+    }
+
+    fn ray(&self, vp: &ViewPlane, s: &CameraSample) -> Option<Ray> {
+        let (ax, ay) = self.aperture.sample(s.lx, s.ly);
+        Some(self.lens_ray(vp, s, ax * self.lens_radius, ay * self.lens_radius))
+    }
+
+    // The ray through the middle of the lens and the focus point.
+    fn focus_ray(&self, vp: &ViewPlane) -> Option<Ray> {
+        let (px, py) = match self.autofocus {
+            None => return None,
+            Some(FocusPoint::Center) => (0.0, 0.0),
+            Some(FocusPoint::Pixel(col, row)) =>
+                (col as f64 + 0.5 - 0.5 * vp.hres as f64,
+                 0.5 * vp.vres as f64 - row as f64 - 0.5),
+        };
+
+        Some(self.lens_ray(vp, &CameraSample { px, py, lx: 0.5, ly: 0.5, t: 0.0 }, 0.0, 0.0))
+    }
+
+    // Focus on the point at the given distance along the focus ray;
+    // the focal plane is perpendicular to w, so only the distance
+    // along -w counts.
+    fn focus_on(&mut self, r: &Ray, distance: f64) {
+        self.focal_plane_distance = distance * r.direction.dot(&-self.core.w);
+    }
+}
+
+// A camera that maps the largest disc that fits in the view plane onto
+// a field of view of up to 360 degrees, with the angle from -w
+// proportional to the distance from the center of the disc. Samples
+// outside the disc are black.
+pub struct FisheyeCamera {
+    pub core: CameraCore,
+    // The full field of view across the disc, in degrees.
+    pub fov: f64,
+}
+
+impl Camera for FisheyeCamera {
+    fn core(&self) -> &CameraCore {
+        &self.core
+    }
+
+    fn set_core(&mut self, core: CameraCore) {
+        self.core = core;
+    }
+
+    fn ray(&self, vp: &ViewPlane, s: &CameraSample) -> Option<Ray> {
+        let radius = 0.5 * (vp.hres.min(vp.vres) as f64);
+        let x = s.px / radius;
+        let y = s.py / radius;
+        let r = (x * x + y * y).sqrt();
+
+        if r > 1.0 {
+            return None;
         }
 
-        img
+        let psi = r * self.fov.to_radians() * 0.5;
+        let (sin_alpha, cos_alpha) = if r > 0.0 { (y / r, x / r) } else { (0.0, 0.0) };
+
+        Some(Ray {
+            direction: psi.sin() * cos_alpha * self.core.u +
+                psi.sin() * sin_alpha * self.core.v -
+                psi.cos() * self.core.w,
+            origin: self.core.eye,
+            time: self.core.shutter_time(s.t),
+        })
+    }
+}
+
+// A panoramic camera with an equirectangular projection: horizontal
+// position on the view plane maps linearly to longitude around v and
+// vertical position to latitude, with -w at the center. With a 360 x
+// 180 degree field of view and a 2:1 view plane this produces an
+// environment map.
+pub struct SphericalCamera {
+    pub core: CameraCore,
+    // The horizontal and vertical fields of view, in degrees.
+    pub horizontal_fov: f64,
+    pub vertical_fov: f64,
+}
+
+impl Camera for SphericalCamera {
+    fn core(&self) -> &CameraCore {
+        &self.core
+    }
+
+    fn set_core(&mut self, core: CameraCore) {
+        self.core = core;
+    }
+
+    fn ray(&self, vp: &ViewPlane, s: &CameraSample) -> Option<Ray> {
+        let x = 2.0 * s.px / (vp.hres as f64);
+        let y = 2.0 * s.py / (vp.vres as f64);
+
+        let lambda = x * self.horizontal_fov.to_radians() * 0.5;
+        let psi = y * self.vertical_fov.to_radians() * 0.5;
+        let phi = PI - lambda;
+        let theta = 0.5 * PI - psi;
+
+        Some(Ray {
+            direction: theta.sin() * phi.sin() * self.core.u +
+                theta.cos() * self.core.v +
+                theta.sin() * phi.cos() * self.core.w,
+            origin: self.core.eye,
+            time: self.core.shutter_time(s.t),
+        })
+    }
+}
+
+// How the two eyes of a stereo camera are aimed.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum Convergence {
+    // Parallel view directions; everything appears in front of the
+    // screen.
+    Parallel,
+    // Both eyes turned to look at the look_at point. Simple, but adds
+    // vertical parallax towards the edges of the image.
+    ToeIn,
+    // Parallel view directions with each eye's view plane shifted so
+    // that the look_at point has no parallax.
+    OffAxis,
+}
+
+// How a stereo camera lays out its two views in the image.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum StereoOutput {
+    // The left eye's view in the left half of the image and the right
+    // eye's view in the right half.
+    SideBySide,
+    // A red/cyan composite: red from the left eye, green and blue from
+    // the right.
+    Anaglyph,
+}
+
+// A pair of thin lens eyes offset along u from a center camera; a lens
+// radius of zero gives pinhole eyes. Both eyes converge on the center
+// camera's look_at point.
+pub struct StereoCamera {
+    pub center: ThinLensCamera,
+    pub interocular_distance: f64,
+    pub convergence: Convergence,
+    pub output: StereoOutput,
+    // The eyes, derived from the settings above.
+    left: ThinLensCamera,
+    right: ThinLensCamera,
+    // How far each eye's view plane is shifted towards the other eye,
+    // in view plane units before zooming.
+    view_shift: f64,
+}
+
+impl StereoCamera {
+    pub fn new(center: ThinLensCamera, interocular_distance: f64,
+               convergence: Convergence, output: StereoOutput) -> StereoCamera {
+        let (left, right, view_shift) =
+            StereoCamera::eyes(&center, interocular_distance, convergence);
+
+        StereoCamera {
+            center,
+            interocular_distance,
+            convergence,
+            output,
+            left,
+            right,
+            view_shift,
+        }
+    }
+
+    fn eyes(center: &ThinLensCamera, interocular_distance: f64, convergence: Convergence)
+            -> (ThinLensCamera, ThinLensCamera, f64) {
+        let core = &center.core;
+        let offset = 0.5 * interocular_distance * core.u;
+        let convergence_distance = (core.look_at - core.eye).norm();
+
+        let eye = |offset: Vector3<f64>| {
+            let look_at = match convergence {
+                Convergence::ToeIn => core.look_at,
+                Convergence::Parallel | Convergence::OffAxis => core.look_at + offset,
+            };
+            ThinLensCamera {
+                core: CameraCore::new(core.eye + offset, look_at, core.up)
+                    .with_shutter(core.shutter_open, core.shutter_close),
+                vp_distance: center.vp_distance,
+                zoom_factor: center.zoom_factor,
+                lens_radius: center.lens_radius,
+                focal_plane_distance: center.focal_plane_distance,
+                aperture: center.aperture.clone(),
+                autofocus: center.autofocus,
+            }
+        };
+
+        let view_shift = match convergence {
+            Convergence::OffAxis =>
+                0.5 * interocular_distance * center.vp_distance / convergence_distance,
+            Convergence::Parallel | Convergence::ToeIn => 0.0,
+        };
+
+        (eye(-offset), eye(offset), view_shift)
+    }
+
+    // The samples for each eye. In side-by-side mode each eye's view is
+    // centered in its half of the view plane.
+    fn eye_samples(&self, vp: &ViewPlane, s: &CameraSample) -> (CameraSample, CameraSample) {
+        let shift = self.view_shift * self.left.zoom_factor / vp.pixel_size;
+        let (left_center, right_center) = match self.output {
+            StereoOutput::SideBySide => (-0.25 * vp.hres as f64, 0.25 * vp.hres as f64),
+            StereoOutput::Anaglyph => (0.0, 0.0),
+        };
+
+        (CameraSample { px: s.px - left_center + shift, ..*s },
+         CameraSample { px: s.px - right_center - shift, ..*s })
+    }
+}
+
+impl Camera for StereoCamera {
+    fn core(&self) -> &CameraCore {
+        &self.center.core
+    }
+
+    fn set_core(&mut self, core: CameraCore) {
+        self.center.core = core;
+        let (left, right, view_shift) =
+            StereoCamera::eyes(&self.center, self.interocular_distance, self.convergence);
+        self.left = left;
+        self.right = right;
+        self.view_shift = view_shift;
+    }
+
+    // In anaglyph mode this is the left eye's ray.
+    fn ray(&self, vp: &ViewPlane, s: &CameraSample) -> Option<Ray> {
+        let (left_sample, right_sample) = self.eye_samples(vp, s);
+
+        if self.output == StereoOutput::SideBySide && s.px >= 0.0 {
+            self.right.ray(vp, &right_sample)
+        } else {
+            self.left.ray(vp, &left_sample)
+        }
+    }
+
+    // Both eyes focus at the distance measured by the left eye.
+    fn focus_ray(&self, vp: &ViewPlane) -> Option<Ray> {
+        self.left.focus_ray(vp)
+    }
+
+    fn focus_on(&mut self, r: &Ray, distance: f64) {
+        self.left.focus_on(r, distance);
+        self.right.focal_plane_distance = self.left.focal_plane_distance;
+        self.center.focal_plane_distance = self.left.focal_plane_distance;
+    }
+
+    fn sample_color(&self, vp: &ViewPlane, s: &CameraSample,
+                    trace: &mut FnMut(&Ray) -> Color) -> Color {
+        match self.output {
+            StereoOutput::SideBySide => match self.ray(vp, s) {
+                Some(r) => trace(&r),
+                None => black(),
+            },
+            StereoOutput::Anaglyph => {
+                let (left_sample, right_sample) = self.eye_samples(vp, s);
+                let left = self.left.ray(vp, &left_sample).map_or(black(), |r| trace(&r));
+                let right = self.right.ray(vp, &right_sample).map_or(black(), |r| trace(&r));
+                Color::new(left.r, right.g, right.b)
+            },
+        }
     }
 }
@@ -0,0 +1,412 @@
+
+use std::fmt::Display;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+use types::*;
+use accumulator::Accumulator;
+use tiles::{Tile, TileOrder};
+use render::pass_ranges;
+
+const HEADER: &'static str = "rebound checkpoint 1";
+
+// Checkpoints hold the settings that determine the rendered image,
+// as "key value" text lines, followed by the accumulation buffer: for
+// each pixel, its sum as three little-endian f64s and its sample count
+// as a little-endian u64. The floats are stored bit for bit so a
+// resumed render continues from exactly the same state.
+pub fn save(path: &str, config: &Config, acc: &Accumulator) -> io::Result<()> {
+    // Write to a temporary file first so an interruption while saving
+    // doesn't destroy the previous checkpoint.
+    let tmp_path = format!("{}.tmp", path);
+
+    {
+        let mut buf = BufWriter::new(File::create(&tmp_path)?);
+
+        writeln!(buf, "{}", HEADER)?;
+        write_settings(&mut buf, config)?;
+        writeln!(buf, "width {}", acc.width)?;
+        writeln!(buf, "height {}", acc.height)?;
+        writeln!(buf, "data")?;
+
+        for (sum, count) in acc.sums.iter().zip(acc.counts.iter()) {
+            for v in &[sum.r, sum.g, sum.b] {
+                buf.write_all(&v.to_bits().to_le_bytes())?;
+            }
+            buf.write_all(&(*count as u64).to_le_bytes())?;
+        }
+
+        buf.flush()?;
+    }
+
+    fs::rename(tmp_path, path)
+}
+
+// Load a checkpoint. The returned configuration is base with the
+// checkpoint's render settings applied, so settings that don't affect
+// the image (output paths, verbosity and so on) come from base.
+pub fn load(path: &str, base: &Config) -> io::Result<(Config, Accumulator)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut config = base.clone();
+    let mut width = 0;
+    let mut height = 0;
+
+    if read_line(&mut reader)? != HEADER {
+        return Err(invalid("not a rebound checkpoint"));
+    }
+
+    loop {
+        let line = read_line(&mut reader)?;
+        if line == "data" {
+            break;
+        }
+
+        let (key, value) = split_setting(&line)?;
+        if !apply_setting(&mut config, key, value)? {
+            match key {
+                "width" => width = parse(value)?,
+                "height" => height = parse(value)?,
+                _ => return Err(invalid(&format!("unknown key: {}", key))),
+            }
+        }
+    }
+
+    if width == 0 || height == 0 {
+        return Err(invalid("missing image size"));
+    }
+
+    let mut acc = Accumulator::new(width, height);
+    let mut word = [0u8; 8];
+    let mut read_word = |reader: &mut BufReader<File>| -> io::Result<u64> {
+        reader.read_exact(&mut word)?;
+        Ok(u64::from_le_bytes(word))
+    };
+
+    for p in 0..(width * height) {
+        acc.sums[p] = Color::new(f64::from_bits(read_word(&mut reader)?),
+                                 f64::from_bits(read_word(&mut reader)?),
+                                 f64::from_bits(read_word(&mut reader)?));
+        acc.counts[p] = read_word(&mut reader)? as usize;
+    }
+
+    // More samples than the settings call for means the data doesn't
+    // belong to them.
+    let samples = pass_ranges(&config).last().map_or(0, |r| r.end);
+    if acc.counts.iter().any(|&c| c > samples) {
+        return Err(invalid(&format!("pixels have more than the {} samples the settings allow",
+                                    samples)));
+    }
+
+    Ok((config, acc))
+}
+
+// Check that a loaded checkpoint's image is the size of the scene it is
+// to be resumed with, which may have changed since it was saved.
+pub fn check_scene(acc: &Accumulator, scene: &Scene) -> io::Result<()> {
+    if acc.width != scene.view_plane.hres || acc.height != scene.view_plane.vres {
+        return Err(invalid(&format!("image is {}x{} but the scene renders {}x{}",
+                                    acc.width, acc.height,
+                                    scene.view_plane.hres, scene.view_plane.vres)));
+    }
+    Ok(())
+}
+
+// Write the settings that determine the rendered image, one "key
+// value" line each.
+pub fn write_settings<W: Write>(w: &mut W, config: &Config) -> io::Result<()> {
+    writeln!(w, "scene_name {}", config.scene_name)?;
+    writeln!(w, "sample_root {}", config.sample_root)?;
+    writeln!(w, "max_depth {}", config.max_depth)?;
+    write_option(w, "passes", config.passes)?;
+    writeln!(w, "tile_size {}", config.tile_size)?;
+    writeln!(w, "tile_order {}", config.tile_order.name())?;
+    writeln!(w, "seed {}", config.seed)?;
+    write_option(w, "region", config.region.map(|r| format!("{},{},{},{}",
+                                                             r.x0, r.y0, r.x1, r.y1)))?;
+    write_option(w, "camera_name", config.camera_name.as_ref())?;
+    Ok(())
+}
+
+// Optional settings are written as "none" or "some" followed by the
+// value, so that no value can be mistaken for a missing one.
+fn write_option<W: Write, T: Display>(w: &mut W, key: &str, value: Option<T>) -> io::Result<()> {
+    match value {
+        Some(v) => writeln!(w, "{} some {}", key, v),
+        None => writeln!(w, "{} none", key),
+    }
+}
+
+fn parse_option<T, F>(value: &str, parse_value: F) -> io::Result<Option<T>>
+    where F: Fn(&str) -> io::Result<T> {
+    if value == "none" {
+        Ok(None)
+    } else if value.starts_with("some ") {
+        parse_value(&value["some ".len()..]).map(Some)
+    } else {
+        Err(invalid(&format!("invalid optional value: {}", value)))
+    }
+}
+
+pub fn split_setting(line: &str) -> io::Result<(&str, &str)> {
+    let mut parts = line.splitn(2, ' ');
+    let key = parts.next().unwrap_or("");
+    let value = parts.next().ok_or_else(|| invalid("missing value"))?;
+    Ok((key, value))
+}
+
+// Apply one setting written by write_settings. Returns false if the key
+// isn't a setting, so callers can handle keys of their own.
+pub fn apply_setting(config: &mut Config, key: &str, value: &str) -> io::Result<bool> {
+    match key {
+        "scene_name" => config.scene_name = String::from(value),
+        "sample_root" => config.sample_root = parse(value)?,
+        "max_depth" => config.max_depth = parse(value)?,
+        "passes" => config.passes = parse_option(value, parse)?,
+        "tile_size" => config.tile_size = parse(value)?,
+        "tile_order" => config.tile_order = TileOrder::from_name(value)
+            .ok_or_else(|| invalid("unknown tile order"))?,
+        "seed" => config.seed = parse(value)?,
+        "region" => config.region = parse_option(value, |v| {
+            Tile::from_corners(v).ok_or_else(|| invalid("bad region"))
+        })?,
+        "camera_name" => config.camera_name = parse_option(value, |v| Ok(String::from(v)))?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn read_line(reader: &mut BufReader<File>) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(invalid("unexpected end of file"));
+    }
+    Ok(String::from(line.trim_end()))
+}
+
+fn parse<T: ::std::str::FromStr>(value: &str) -> io::Result<T> {
+    value.parse().map_err(|_| invalid(&format!("invalid value: {}", value)))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("bad checkpoint: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use nalgebra::{Point3, Vector3};
+    use cameras::PinholeCamera;
+    use shapes::plane::Plane;
+    use shapes::sphere::Sphere;
+    use materials::lambertian::Lambertian;
+    use materials::emissive::Emissive;
+    use render;
+
+    fn temp_path(name: &str) -> String {
+        let path = env::temp_dir()
+            .join(format!("rebound-checkpoint-test-{}-{}", name, ::std::process::id()));
+        String::from(path.to_str().unwrap())
+    }
+
+    fn small_scene(config: &Config) -> Scene {
+        let view_plane = ViewPlane {
+            hres: 40,
+            vres: 30,
+            pixel_size: 1.0,
+        };
+        let cam = PinholeCamera::with_fov(
+            CameraCore::new(
+                Vector3::new(0.0, 1.0, 4.0),
+                Vector3::new(0.0, 0.5, 0.0),
+                Vector3::new(0.0, 1.0, 0.0),
+                ),
+            &view_plane, 50.0);
+
+        let objects: Vec<Box<Intersectable>> = vec![
+            Box::new(Plane {
+                origin: Point3::new(0.0, 0.0, 0.0),
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                material: Box::new(Lambertian { albedo: Color::all(0.5) }),
+            }),
+            Box::new(Sphere {
+                center: Vector3::new(0.0, 0.5, 0.0),
+                radius: 0.5,
+                material: Box::new(Lambertian { albedo: Color::new(0.8, 0.4, 0.2) }),
+            }),
+            Box::new(Sphere {
+                center: Vector3::new(0.0, 6.0, 0.0),
+                radius: 3.0,
+                material: Box::new(Emissive { color: Color::all(1.0) }),
+            }),
+        ];
+
+        let mut scene = Scene::new(objects, Color::all(0.2), Box::new(cam), config, view_plane);
+        scene.prepare();
+        scene
+    }
+
+    // Saves a checkpoint after the given number of tiles, then cancels
+    // the render.
+    struct Interrupt {
+        path: String,
+        config: Config,
+        tiles: AtomicUsize,
+        after: usize,
+        cancel: CancelToken,
+    }
+
+    impl RenderObserver for Interrupt {
+        fn tile_finished(&self, _tile: &Tile, acc: &Accumulator) {
+            if self.tiles.fetch_add(1, Ordering::SeqCst) + 1 == self.after {
+                save(&self.path, &self.config, acc).unwrap();
+                self.cancel.cancel();
+            }
+        }
+    }
+
+    fn write_image(path: &str, img: Image) -> Vec<u8> {
+        img.write(&mut File::create(path).unwrap());
+        let data = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        data
+    }
+
+    // Render the scene uninterrupted, and again interrupted after the
+    // given number of tiles and resumed from the checkpoint, and check
+    // the images are identical.
+    fn check_resume(name: &str, config: &Config, after: usize) {
+        let path = temp_path(name);
+        let image_path = format!("{}.ppm", path);
+
+        let scene = small_scene(config);
+        let (img, _) = render::render(&scene, scene.camera.as_ref(), &NullObserver,
+                                      &CancelToken::new());
+        let expected = write_image(&image_path, img);
+
+        let interrupt = Interrupt {
+            path: path.clone(),
+            config: config.clone(),
+            tiles: AtomicUsize::new(0),
+            after,
+            cancel: CancelToken::new(),
+        };
+        render::render(&scene, scene.camera.as_ref(), &interrupt, &interrupt.cancel);
+
+        let (resumed_config, acc) = load(&path, &Config::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+        // Both test configurations take four samples per pixel.
+        assert!(acc.counts.iter().any(|&c| c < 4));
+
+        let scene = small_scene(&resumed_config);
+        check_scene(&acc, &scene).unwrap();
+        let (img, _) = render::render_from(&scene, scene.camera.as_ref(), acc, &NullObserver,
+                                           &CancelToken::new());
+        assert!(write_image(&image_path, img) == expected);
+    }
+
+    fn test_config() -> Config {
+        let mut config = Config::default();
+        config.scene_name = String::from("small");
+        config.sample_root = 2;
+        config.max_depth = 3;
+        config.tile_size = 8;
+        config.seed = 17;
+        config
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut config = test_config();
+        config.passes = Some(3);
+        config.tile_order = TileOrder::Hilbert;
+        config.region = Some(Tile { x0: 1, y0: 2, x1: 3, y1: 3 });
+        // Not to be confused with no camera.
+        config.camera_name = Some(String::from("none"));
+
+        let mut acc = Accumulator::new(3, 2);
+        for (i, (sum, count)) in acc.sums.iter_mut().zip(acc.counts.iter_mut()).enumerate() {
+            *sum = Color::new(0.1 * i as f64, 1.0 / 3.0, 1e-300);
+            *count = i % 4;
+        }
+
+        let path = temp_path("round-trip");
+        save(&path, &config, &acc).unwrap();
+        let mut base = Config::default();
+        base.output_file = String::from("base.ppm");
+        let (loaded, loaded_acc) = load(&path, &base).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.scene_name, config.scene_name);
+        assert_eq!(loaded.sample_root, config.sample_root);
+        assert_eq!(loaded.max_depth, config.max_depth);
+        assert_eq!(loaded.passes, config.passes);
+        assert_eq!(loaded.tile_size, config.tile_size);
+        assert_eq!(loaded.tile_order.name(), config.tile_order.name());
+        assert_eq!(loaded.seed, config.seed);
+        assert_eq!(loaded.region, config.region);
+        assert_eq!(loaded.camera_name, config.camera_name);
+        assert_eq!(loaded.output_file, base.output_file);
+
+        assert_eq!((loaded_acc.width, loaded_acc.height), (3, 2));
+        assert_eq!(loaded_acc.counts, acc.counts);
+        for (a, b) in loaded_acc.sums.iter().zip(acc.sums.iter()) {
+            assert_eq!((a.r.to_bits(), a.g.to_bits(), a.b.to_bits()),
+                       (b.r.to_bits(), b.g.to_bits(), b.b.to_bits()));
+        }
+    }
+
+    #[test]
+    fn no_camera_or_region() {
+        let config = test_config();
+        let path = temp_path("no-camera");
+        save(&path, &config, &Accumulator::new(1, 1)).unwrap();
+        let mut base = Config::default();
+        base.camera_name = Some(String::from("top"));
+        base.region = Some(Tile { x0: 0, y0: 0, x1: 1, y1: 1 });
+        let (loaded, _) = load(&path, &base).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.camera_name, None);
+        assert_eq!(loaded.region, None);
+    }
+
+    #[test]
+    fn resume() {
+        check_resume("resume", &test_config(), 7);
+    }
+
+    #[test]
+    fn resume_progressive() {
+        let mut config = test_config();
+        config.passes = Some(4);
+        check_resume("resume-progressive", &config, 30);
+    }
+
+    #[test]
+    fn mismatched_scene() {
+        let config = test_config();
+        let scene = small_scene(&config);
+        assert!(check_scene(&Accumulator::new(40, 30), &scene).is_ok());
+        let e = check_scene(&Accumulator::new(30, 40), &scene).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn too_many_samples() {
+        // Four samples per pixel are allowed, so five can't have come
+        // from these settings.
+        let config = test_config();
+        let mut acc = Accumulator::new(2, 2);
+        acc.counts[3] = 5;
+
+        let path = temp_path("too-many-samples");
+        save(&path, &config, &acc).unwrap();
+        let e = load(&path, &Config::default()).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
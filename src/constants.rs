@@ -3,3 +3,13 @@ pub const T_MIN: f64 = 0.0005;
 pub const DEFAULT_OUTPUT_FILENAME: &'static str = "output.ppm";
 pub const DEFAULT_SAMPLE_ROOT: usize = 1;
 pub const DEFAULT_MAX_DEPTH: usize = 3;
+pub const DEFAULT_TILE_SIZE: usize = 32;
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 60;
+pub const DEFAULT_TILE_ORDER: &'static str = "scanline";
+pub const DEFAULT_CAMERA_NAME: &'static str = "main";
+pub const DEFAULT_WORKER_ADDRESS: &'static str = "127.0.0.1:7878";
+pub const DEFAULT_WORKER_TIMEOUT: u64 = 300;
+pub const DEFAULT_MODELS_DIR: &'static str = "models";
+// World units are meters for the purposes of physical lens parameters.
+pub const MILLIMETERS_PER_UNIT: f64 = 1000.0;
+pub const FULL_FRAME_SENSOR_HEIGHT: f64 = 24.0;
@@ -0,0 +1,34 @@
+
+extern crate nalgebra;
+extern crate rand;
+extern crate rayon;
+extern crate samplers;
+extern crate tobj;
+
+pub mod types;
+pub mod materials;
+pub mod cameras;
+pub mod shapes;
+pub mod constants;
+pub mod util;
+pub mod scene;
+pub mod scenes;
+pub mod render;
+pub mod service;
+pub mod stats;
+pub mod tiles;
+pub mod accumulator;
+pub mod checkpoint;
+pub mod network;
+pub mod aperture;
+pub mod animation;
+pub mod loaders;
+pub mod bvh;
+
+pub use types::{Scene, Camera, CameraCore, Intersectable, Material, Image,
+                Color, Config, Hit, Ray, ScatterResult, ViewPlane, CameraSample,
+                RenderObserver, NullObserver, CancelToken};
+pub use service::{RenderService, Command, Event};
+pub use stats::{RayStats, RenderStats};
+pub use tiles::{Tile, TileOrder};
+pub use accumulator::Accumulator;
@@ -0,0 +1,222 @@
+
+extern crate nalgebra;
+
+use nalgebra::{Vector2, Vector3};
+
+use std::io;
+
+use tobj;
+
+use types::*;
+use shapes::triangle::TriangleMesh;
+use materials::lambertian::Lambertian;
+use materials::metal::Metal;
+use materials::emissive::Emissive;
+
+// The meshes in an OBJ file, and any problems with it that didn't stop
+// it loading, for the caller to report.
+pub struct ObjModel {
+    pub meshes: Vec<TriangleMesh>,
+    pub warnings: Vec<String>,
+}
+
+// Load the models in a Wavefront OBJ file as triangle meshes, one per
+// object and material. Faces with more than three vertices are split
+// into triangles. MTL materials are mapped onto rebound's materials by
+// to_material; faces without one get default_material, as do all faces
+// if the MTL file can't be loaded. A model whose normals or texture
+// coordinates are given for only some of its faces is loaded without
+// them.
+pub fn load_obj(path: &str, default_material: &Fn() -> Box<Material>)
+                -> io::Result<ObjModel> {
+    let invalid = |what: &str, e: tobj::LoadError|
+        io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}: {}", path, what, e));
+
+    let mut warnings = vec![];
+    let (models, materials) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)
+        .map_err(|e| invalid("could not load OBJ file", e))?;
+    let materials = materials.unwrap_or_else(|e| {
+        warnings.push(format!("{}: could not load MTL file, using default materials: {}",
+                              path, e));
+        vec![]
+    });
+
+    let meshes = models.into_iter()
+        .filter(|m| !m.mesh.indices.is_empty())
+        .map(|m| {
+            let (name, mesh) = (m.name, m.mesh);
+            let material = match mesh.material_id.and_then(|id| materials.get(id)) {
+                Some(m) => to_material(m),
+                None => default_material(),
+            };
+
+            // tobj only gives normals and texture coordinates for the
+            // vertices of faces that have them.
+            let vertex_count = mesh.positions.len() / 3;
+            let mut complete = |what: &str, count: usize| {
+                if count == 0 || count == vertex_count {
+                    true
+                } else {
+                    warnings.push(format!("{}: model {} has {} for only some of its faces, \
+                                           ignoring them", path, name, what));
+                    false
+                }
+            };
+            let has_normals = complete("normals", mesh.normals.len() / 3);
+            let has_uvs = complete("texture coordinates", mesh.texcoords.len() / 2);
+            let has_colors = complete("vertex colors", mesh.vertex_color.len() / 3);
+
+            let positions = mesh.positions.chunks(3)
+                .map(|p| Vector3::new(p[0], p[1], p[2]))
+                .collect();
+            let normals = mesh.normals.chunks(3)
+                .filter(|_| has_normals)
+                .map(|n| Vector3::new(n[0], n[1], n[2]))
+                // Zero normals stay zero rather than becoming NaNs;
+                // TriangleMesh shades with the face normal where the
+                // interpolated normal is zero.
+                .map(|n| if n.norm() > 0.0 { n.normalize() } else { n })
+                .collect();
+            let uvs = mesh.texcoords.chunks(2)
+                .filter(|_| has_uvs)
+                .map(|t| Vector2::new(t[0], t[1]))
+                .collect();
+            let vertex_colors = mesh.vertex_color.chunks(3)
+                .filter(|_| has_colors)
+                .map(|c| Color::new(c[0], c[1], c[2]))
+                .collect::<Vec<Color>>();
+            let indices = mesh.indices.chunks(3)
+                .map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+                .collect();
+
+            let mesh = TriangleMesh::new(positions, normals, uvs, indices, material);
+            if vertex_colors.is_empty() {
+                mesh
+            } else {
+                mesh.with_colors(vertex_colors)
+            }
+        })
+        .collect();
+
+    Ok(ObjModel { meshes, warnings })
+}
+
+// Materials with an emission color (Ke) become Emissive. Those with
+// the MTL reflection illumination models (3, 5, 6 and 7) or a PBR
+// metallic value (Pm) of at least 0.5 become Metal, colored by their
+// specular color (Ks) if they have one, with the gloss taken from
+// their roughness (Pr) or shininess exponent (Ns, 0 to 1000). Anything
+// else is Lambertian with the diffuse color (Kd).
+pub fn to_material(m: &tobj::Material) -> Box<Material> {
+    let param = |key: &str| -> Option<Vec<f64>> {
+        m.unknown_param.get(key)
+            .map(|v| v.split_whitespace().filter_map(|f| f.parse().ok()).collect())
+    };
+    let color = |c: &[f64; 3]| Color::new(c[0], c[1], c[2]);
+
+    if let Some(ke) = param("Ke") {
+        if ke.len() == 3 && ke.iter().any(|&c| c > 0.0) {
+            return Box::new(Emissive {
+                color: Color::new(ke[0], ke[1], ke[2]),
+            });
+        }
+    }
+
+    let reflective = match m.illumination_model {
+        Some(3) | Some(5) | Some(6) | Some(7) => true,
+        _ => false,
+    };
+    let metallic = param("Pm").and_then(|v| v.first().cloned()).unwrap_or(0.0) >= 0.5;
+
+    if reflective || metallic {
+        let gloss = match param("Pr").and_then(|v| v.first().cloned()) {
+            Some(roughness) => roughness,
+            None => 1.0 - (m.shininess / 1000.0).max(0.0).min(1.0).sqrt(),
+        };
+        let albedo = if m.specular.iter().any(|&c| c > 0.0) { &m.specular } else { &m.diffuse };
+
+        Box::new(Metal {
+            albedo: color(albedo),
+            gloss: gloss.max(0.0).min(1.0),
+        })
+    } else {
+        Box::new(Lambertian {
+            albedo: color(&m.diffuse),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use materials::lambertian::Lambertian;
+
+    // A unit square's corners, one normal and three texture
+    // coordinates, for the faces of each test to use.
+    const VERTICES: &'static str = "v 0 0 0
+v 1 0 0
+v 0 1 0
+v 1 1 0
+vn 0 0 2
+vt 0 0
+vt 1 0
+vt 0 1
+";
+
+    fn load(name: &str, faces: &str) -> ObjModel {
+        let path = env::temp_dir()
+            .join(format!("rebound-obj-test-{}-{}.obj", name, ::std::process::id()));
+        fs::write(&path, format!("{}{}", VERTICES, faces)).unwrap();
+        let default_material = || Box::new(Lambertian {
+            albedo: Color::all(1.0),
+        }) as Box<Material>;
+        let model = load_obj(path.to_str().unwrap(), &default_material);
+        fs::remove_file(&path).unwrap();
+        model.unwrap()
+    }
+
+    #[test]
+    fn normals() {
+        let model = load("normals", "f 1//1 2//1 3//1\nf 2//1 4//1 3//1\n");
+        assert!(model.warnings.is_empty());
+        assert_eq!(model.meshes.len(), 1);
+
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.triangle_count(), 2);
+        assert_eq!(mesh.normals.len(), mesh.positions.len());
+        for n in mesh.normals.iter() {
+            assert_eq!(*n, Vector3::new(0.0, 0.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn mixed_normals() {
+        let model = load("mixed-normals", "f 1//1 2//1 3//1\nf 2 4 3\n");
+        assert_eq!(model.warnings.len(), 1);
+
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.triangle_count(), 2);
+        assert!(mesh.normals.is_empty());
+    }
+
+    #[test]
+    fn mixed_texture_coordinates() {
+        let model = load("mixed-uvs", "f 1/1 2/2 3/3\nf 2 4 3\n");
+        assert_eq!(model.warnings.len(), 1);
+
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.triangle_count(), 2);
+        assert!(mesh.uvs.is_empty());
+    }
+
+    #[test]
+    fn missing_mtl_file() {
+        let model = load("missing-mtl", "mtllib rebound-missing.mtl\nusemtl red\nf 1 2 3\n");
+        assert_eq!(model.warnings.len(), 1);
+        assert!(model.warnings[0].contains("MTL"));
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.meshes[0].triangle_count(), 1);
+    }
+}
@@ -0,0 +1,414 @@
+
+extern crate nalgebra;
+
+use nalgebra::{Vector2, Vector3};
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::str::SplitWhitespace;
+
+use types::*;
+use shapes::triangle::TriangleMesh;
+
+// Load a PLY file in ASCII or binary little-endian format as a triangle
+// mesh. Vertex positions are required; normals (nx, ny, nz), colors
+// (red, green, blue) and texture coordinates (u, v, or s, t) are used
+// if present. Faces with more than three vertices are split into
+// triangles, and any other elements and properties are skipped.
+pub fn load_ply(path: &str, material: Box<Material>) -> io::Result<TriangleMesh> {
+    let mut data = vec![];
+    File::open(path).and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData,
+                                             format!("{}: bad PLY file: {}", path, msg));
+
+    let (header, body_start) = read_header(&data).map_err(|e| invalid(&e))?;
+    let text = match header.format {
+        Format::Ascii => String::from_utf8_lossy(&data[body_start..]).into_owned(),
+        Format::BinaryLittleEndian => String::new(),
+    };
+    let mut values = match header.format {
+        Format::Ascii => Values::Ascii(text.split_whitespace()),
+        Format::BinaryLittleEndian => Values::Binary(&data[body_start..]),
+    };
+
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    let mut colors = vec![];
+    let mut indices = vec![];
+
+    for element in header.elements.iter() {
+        match element.name.as_str() {
+            "vertex" => {
+                let find = |names: &[&str]| names.iter()
+                    .map(|n| element.properties.iter().position(|p| p.name == *n))
+                    .collect::<Option<Vec<usize>>>();
+                let position = find(&["x", "y", "z"])
+                    .ok_or_else(|| invalid("vertices have no x, y and z"))?;
+                let normal = find(&["nx", "ny", "nz"]);
+                let color = find(&["red", "green", "blue"]);
+                let uv = find(&["u", "v"]).or_else(|| find(&["s", "t"]))
+                    .or_else(|| find(&["texture_u", "texture_v"]));
+
+                // Integer colors run from 0 to 255, floating point ones
+                // from 0 to 1.
+                let color_scale = match color {
+                    Some(ref c) => match element.properties[c[0]].kind {
+                        PropertyKind::Scalar(Scalar::Float32) |
+                        PropertyKind::Scalar(Scalar::Float64) => 1.0,
+                        _ => 1.0 / 255.0,
+                    },
+                    None => 1.0,
+                };
+
+                for _ in 0..element.count {
+                    let v = read_element(&mut values, element).map_err(|e| invalid(&e))?;
+                    let vector = |i: &Vec<usize>| Vector3::new(v[i[0]], v[i[1]], v[i[2]]);
+
+                    positions.push(vector(&position));
+                    if let Some(ref i) = normal {
+                        // Zero normals stay zero rather than becoming
+                        // NaNs; see load_obj.
+                        let n = vector(i);
+                        normals.push(if n.norm() > 0.0 { n.normalize() } else { n });
+                    }
+                    if let Some(ref i) = color {
+                        colors.push(Color::new(v[i[0]], v[i[1]], v[i[2]]) * color_scale);
+                    }
+                    if let Some(ref i) = uv {
+                        uvs.push(Vector2::new(v[i[0]], v[i[1]]));
+                    }
+                }
+            },
+            "face" => {
+                let list = element.properties.iter()
+                    .position(|p| p.name == "vertex_indices" || p.name == "vertex_index")
+                    .ok_or_else(|| invalid("faces have no vertex_indices"))?;
+
+                for _ in 0..element.count {
+                    let mut face = vec![];
+                    for (i, p) in element.properties.iter().enumerate() {
+                        let v = read_property(&mut values, p).map_err(|e| invalid(&e))?;
+                        if i == list {
+                            face = v.iter().map(|&x| x as usize).collect();
+                        }
+                    }
+
+                    // A fan of triangles around the first vertex.
+                    for k in 1..face.len().saturating_sub(1) {
+                        indices.push([face[0], face[k], face[k + 1]]);
+                    }
+                }
+            },
+            _ => {
+                for _ in 0..element.count {
+                    read_element(&mut values, element).map_err(|e| invalid(&e))?;
+                }
+            },
+        }
+    }
+
+    if let Some(&i) = indices.iter().flat_map(|t: &[usize; 3]| t.iter()).find(|&&i| i >= positions.len()) {
+        return Err(invalid(&format!("vertex index {} out of range", i)));
+    }
+
+    let mesh = TriangleMesh::new(positions, normals, uvs, indices, material);
+    Ok(if colors.is_empty() { mesh } else { mesh.with_colors(colors) })
+}
+
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+#[derive(Clone)]
+#[derive(Copy)]
+enum Scalar {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+enum PropertyKind {
+    Scalar(Scalar),
+    // A list of values preceded by its length.
+    List(Scalar, Scalar),
+}
+
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+impl Scalar {
+    fn from_name(name: &str) -> Option<Scalar> {
+        match name {
+            "char" | "int8" => Some(Scalar::Int8),
+            "uchar" | "uint8" => Some(Scalar::UInt8),
+            "short" | "int16" => Some(Scalar::Int16),
+            "ushort" | "uint16" => Some(Scalar::UInt16),
+            "int" | "int32" => Some(Scalar::Int32),
+            "uint" | "uint32" => Some(Scalar::UInt32),
+            "float" | "float32" => Some(Scalar::Float32),
+            "double" | "float64" => Some(Scalar::Float64),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match *self {
+            Scalar::Int8 | Scalar::UInt8 => 1,
+            Scalar::Int16 | Scalar::UInt16 => 2,
+            Scalar::Int32 | Scalar::UInt32 | Scalar::Float32 => 4,
+            Scalar::Float64 => 8,
+        }
+    }
+}
+
+// Parse the header, returning it and the offset of the data after it.
+fn read_header(data: &[u8]) -> Result<(Header, usize), String> {
+    let mut pos = 0;
+    let mut next_line = || -> Result<String, String> {
+        let end = data[pos..].iter().position(|&b| b == b'\n')
+            .ok_or_else(|| String::from("truncated header"))?;
+        let line = String::from_utf8_lossy(&data[pos..pos + end]).trim().to_string();
+        pos += end + 1;
+        Ok(line)
+    };
+
+    if next_line()? != "ply" {
+        return Err(String::from("not a PLY file"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    loop {
+        let line = next_line()?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.first() {
+            Some(&"end_header") => break,
+            Some(&"format") => format = match fields.get(1) {
+                Some(&"ascii") => Some(Format::Ascii),
+                Some(&"binary_little_endian") => Some(Format::BinaryLittleEndian),
+                _ => return Err(format!("unsupported format: {}", line)),
+            },
+            Some(&"element") if fields.len() == 3 => elements.push(Element {
+                name: String::from(fields[1]),
+                count: fields[2].parse().map_err(|_| format!("bad element: {}", line))?,
+                properties: vec![],
+            }),
+            Some(&"property") => {
+                let scalar = |name: &str| Scalar::from_name(name)
+                    .ok_or_else(|| format!("unknown property type: {}", name));
+                let property = match fields.len() {
+                    3 => Property {
+                        name: String::from(fields[2]),
+                        kind: PropertyKind::Scalar(scalar(fields[1])?),
+                    },
+                    5 if fields[1] == "list" => Property {
+                        name: String::from(fields[4]),
+                        kind: PropertyKind::List(scalar(fields[2])?, scalar(fields[3])?),
+                    },
+                    _ => return Err(format!("bad property: {}", line)),
+                };
+                elements.last_mut()
+                    .ok_or_else(|| String::from("property before any element"))?
+                    .properties.push(property);
+            },
+            Some(&"comment") | Some(&"obj_info") | None => (),
+            _ => return Err(format!("unexpected header line: {}", line)),
+        }
+    }
+
+    let format = format.ok_or_else(|| String::from("no format line"))?;
+    Ok((Header { format, elements }, pos))
+}
+
+// The element data following the header, read one value at a time.
+enum Values<'a> {
+    Ascii(SplitWhitespace<'a>),
+    Binary(&'a [u8]),
+}
+
+impl<'a> Values<'a> {
+    fn next(&mut self, scalar: Scalar) -> Result<f64, String> {
+        match *self {
+            Values::Ascii(ref mut tokens) => {
+                let token = tokens.next().ok_or_else(|| String::from("truncated data"))?;
+                token.parse().map_err(|_| format!("bad value: {}", token))
+            },
+            Values::Binary(ref mut bytes) => {
+                let size = scalar.size();
+                if bytes.len() < size {
+                    return Err(String::from("truncated data"));
+                }
+                let (b, rest) = bytes.split_at(size);
+                *bytes = rest;
+
+                let mut word = [0u8; 8];
+                word[..size].copy_from_slice(b);
+                let v = u64::from_le_bytes(word);
+                Ok(match scalar {
+                    Scalar::Int8 => v as u8 as i8 as f64,
+                    Scalar::UInt8 => v as u8 as f64,
+                    Scalar::Int16 => v as u16 as i16 as f64,
+                    Scalar::UInt16 => v as u16 as f64,
+                    Scalar::Int32 => v as u32 as i32 as f64,
+                    Scalar::UInt32 => v as u32 as f64,
+                    Scalar::Float32 => f32::from_bits(v as u32) as f64,
+                    Scalar::Float64 => f64::from_bits(v),
+                })
+            },
+        }
+    }
+}
+
+// The values of one property: a single value for scalars, or all of a
+// list's values.
+fn read_property(values: &mut Values, property: &Property) -> Result<Vec<f64>, String> {
+    match property.kind {
+        PropertyKind::Scalar(s) => Ok(vec![values.next(s)?]),
+        PropertyKind::List(count_type, item_type) => {
+            let count = values.next(count_type)? as usize;
+            (0..count).map(|_| values.next(item_type)).collect()
+        },
+    }
+}
+
+// The values of an element's scalar properties, in order. List
+// properties are read but give 0, so that values line up with the
+// element's properties.
+fn read_element(values: &mut Values, element: &Element) -> Result<Vec<f64>, String> {
+    let mut v = Vec::with_capacity(element.properties.len());
+    for p in element.properties.iter() {
+        let pv = read_property(values, p)?;
+        v.push(match p.kind {
+            PropertyKind::Scalar(_) => pv[0],
+            PropertyKind::List(_, _) => 0.0,
+        });
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use materials::lambertian::Lambertian;
+
+    const HEADER: &'static str = "element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+
+    // Positions, normals and colors of a unit square, the first vertex
+    // with a zero normal.
+    const VERTICES: [[f64; 9]; 4] = [
+        [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 255.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 255.0, 0.0],
+        [1.0, 1.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 255.0],
+        [0.0, 1.0, 0.0, 0.0, 0.0, 2.0, 51.0, 51.0, 51.0],
+    ];
+
+    fn ascii_file() -> Vec<u8> {
+        let mut s = format!("ply\nformat ascii 1.0\ncomment test\n{}", HEADER);
+        for v in VERTICES.iter() {
+            let fields: Vec<String> = v.iter().map(|f| f.to_string()).collect();
+            s += &format!("{}\n", fields.join(" "));
+        }
+        s += "4 0 1 2 3\n";
+        s.into_bytes()
+    }
+
+    fn binary_file() -> Vec<u8> {
+        let mut data = format!("ply\nformat binary_little_endian 1.0\n{}", HEADER).into_bytes();
+        for v in VERTICES.iter() {
+            for f in v[..6].iter() {
+                data.extend_from_slice(&(*f as f32).to_bits().to_le_bytes());
+            }
+            data.extend(v[6..].iter().map(|&c| c as u8));
+        }
+        data.push(4);
+        for i in 0..4i32 {
+            data.extend_from_slice(&i.to_le_bytes());
+        }
+        data
+    }
+
+    fn load(name: &str, data: &[u8]) -> io::Result<TriangleMesh> {
+        let path = env::temp_dir()
+            .join(format!("rebound-ply-test-{}-{}.ply", name, ::std::process::id()));
+        fs::write(&path, data).unwrap();
+        let mesh = load_ply(path.to_str().unwrap(), Box::new(Lambertian {
+            albedo: Color::all(1.0),
+        }));
+        fs::remove_file(&path).unwrap();
+        mesh
+    }
+
+    fn check_square(mesh: &TriangleMesh) {
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.positions.len(), 4);
+        for (p, v) in mesh.positions.iter().zip(VERTICES.iter()) {
+            assert_eq!(*p, Vector3::new(v[0], v[1], v[2]));
+        }
+
+        assert_eq!(mesh.normals[0], Vector3::zeros());
+        for n in mesh.normals[1..].iter() {
+            assert_eq!(*n, Vector3::new(0.0, 0.0, 1.0));
+        }
+
+        assert_eq!(mesh.colors.len(), 4);
+        assert_eq!((mesh.colors[0].r, mesh.colors[0].g, mesh.colors[0].b), (1.0, 0.0, 0.0));
+        assert_eq!((mesh.colors[3].r, mesh.colors[3].g, mesh.colors[3].b), (0.2, 0.2, 0.2));
+    }
+
+    #[test]
+    fn ascii() {
+        check_square(&load("ascii", &ascii_file()).unwrap());
+    }
+
+    #[test]
+    fn binary_little_endian() {
+        check_square(&load("binary", &binary_file()).unwrap());
+    }
+
+    #[test]
+    fn truncated() {
+        for &(name, ref data) in [("ascii-truncated", ascii_file()),
+                                  ("binary-truncated", binary_file())].iter() {
+            let e = load(name, &data[..data.len() - 3]).err().expect("truncated file loaded");
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
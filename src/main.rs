@@ -1,40 +1,157 @@
 
 use std::fs::File;
+use std::io::Write;
+use std::process;
+use std::time::{Duration, Instant};
+
+extern crate rebound;
+
+use rebound::scenes;
+use rebound::render;
+use rebound::checkpoint;
+use rebound::network;
+use rebound::animation;
+use rebound::util;
+use rebound::{Accumulator, CancelToken, Config, Scene};
 
-extern crate nalgebra;
-extern crate rayon;
-extern crate samplers;
-
-mod types;
-mod materials;
-mod cameras;
-mod shapes;
-mod constants;
-mod util;
-mod scene;
-mod scenes;
 mod args;
+mod progress;
 
 fn main() {
-    let config = args::config_from_args();
+    let mut config = args::config_from_args();
+    let mut resume_acc = None;
+
+    if let Some(path) = config.resume_file.clone() {
+        let (c, acc) = checkpoint::load(&path, &config).expect("Could not load checkpoint");
+        config = c;
+        resume_acc = Some(acc);
+    }
 
     if !config.quiet {
         config.show();
     }
 
-    let build_scene = scenes::lookup_scene(&config.scene_name).expect("Invalid scene name");
+    // The scene name was checked when parsing the arguments, but may have
+    // come from a checkpoint since.
+    let build_scene = match scenes::lookup_scene(&config.scene_name) {
+        Some(b) => b,
+        None => args::usage_error(&format!("Unknown scene {} (scenes: {})", config.scene_name,
+                                           scenes::scene_names().join(", "))),
+    };
+
+    let mut s = match build_scene(&config) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Could not build scene {}: {}", config.scene_name, e);
+            process::exit(1);
+        },
+    };
+
+    if !config.quiet {
+        for w in s.warnings.iter() {
+            eprintln!("Warning: {}", w);
+        }
+    }
+
+    let bvh_build = s.prepare();
+
+    if let Some(ref name) = config.camera_name {
+        if !s.select_camera(name) {
+            args::usage_error(&format!("Scene {} has no camera named {} (cameras: {})",
+                                       config.scene_name, name, s.camera_names().join(", ")));
+        }
+    }
+
+    if let Err(e) = render::check_region(&s) {
+        args::usage_error(&e);
+    }
+
+    if config.all_cameras {
+        // Each camera is written to output and statistics files named
+        // after it.
+        let names = s.camera_names();
+        for (i, name) in names.iter().enumerate() {
+            s.select_camera(name);
+
+            let mut camera_config = config.clone();
+            camera_config.camera_name = Some(name.clone());
+            camera_config.output_file = util::path_with_suffix(&config.output_file, name);
+            camera_config.stats_file = config.stats_file.as_ref()
+                .map(|p| util::path_with_suffix(p, name));
 
-    let s = build_scene(&config);
-    let img = s.camera.render(&s);
+            if !config.quiet {
+                println!("Camera {} ({} of {}).", name, i + 1, names.len());
+            }
+            render_camera(&mut s, &camera_config, None, bvh_build);
+        }
+    } else {
+        render_camera(&mut s, &config, resume_acc, bvh_build);
+    }
+}
+
+fn render_camera(s: &mut Scene, config: &Config, resume_acc: Option<Accumulator>,
+                 bvh_build: Duration) {
+    match config.animation.clone() {
+        None => render_frame(s, config, resume_acc, bvh_build),
+        Some(anim) => {
+            // Each frame is written to its own numbered output and
+            // statistics files.
+            let base = s.camera.core().clone();
+            for frame in 0..anim.frames {
+                s.camera.set_core(anim.core_at(&base, frame));
+                s.autofocus();
+
+                let mut frame_config = config.clone();
+                frame_config.output_file = animation::frame_path(&config.output_file, frame);
+                frame_config.stats_file = config.stats_file.as_ref()
+                    .map(|p| animation::frame_path(p, frame));
+
+                if !config.quiet {
+                    println!("Frame {} of {}.", frame + 1, anim.frames);
+                }
+                render_frame(s, &frame_config, None, bvh_build);
+            }
+            s.camera.set_core(base);
+            s.autofocus();
+        },
+    }
+}
+
+fn render_frame(s: &Scene, config: &Config, resume_acc: Option<Accumulator>,
+                bvh_build: Duration) {
+    let observer = progress::CliObserver::new(config, render::region(s).pixel_count());
+    let acc = match resume_acc {
+        Some(acc) => {
+            checkpoint::check_scene(&acc, s).expect("Could not resume checkpoint");
+            acc
+        },
+        None => Accumulator::new(s.view_plane.hres, s.view_plane.vres),
+    };
+
+    let (img, mut stats) = if config.workers.is_empty() {
+        render::render_from(s, s.camera.as_ref(), acc, &observer, &CancelToken::new())
+    } else {
+        network::render_from(s, &config.workers, acc, &observer, &CancelToken::new())
+            .expect("Distributed render failed")
+    };
 
     if !config.quiet {
         println!("Writing output file.");
     }
 
+    let output_start = Instant::now();
     let mut output_file = File::create(config.output_file.clone()).unwrap();
-    img.write(&mut output_file);
+    config.output_image(img).write(&mut output_file);
+    stats.output = output_start.elapsed();
+    stats.bvh_build = bvh_build;
 
     if !config.quiet {
         println!("Output written to {}", config.output_file);
+        stats.show();
+    }
+
+    if let Some(ref path) = config.stats_file {
+        let mut stats_file = File::create(path).unwrap();
+        stats_file.write_all(stats.to_json().as_bytes()).unwrap();
     }
 }
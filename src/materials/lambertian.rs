@@ -14,14 +14,15 @@ impl Material for Lambertian {
         black()
     }
 
-    fn scatter(&self, _r: &Ray, hit: &Hit, sv: &Vector3<f64>) -> Option<ScatterResult> {
+    fn scatter(&self, r: &Ray, hit: &Hit, sv: &Vector3<f64>) -> Option<ScatterResult> {
         let target = hit.point + hit.normal + sv;
         Some(ScatterResult {
             ray: Ray {
                 origin: hit.point,
                 direction: target - hit.point,
+                time: r.time,
             },
-            attenuate: self.albedo,
+            attenuate: self.albedo * hit.color,
         })
     }
 }
@@ -25,8 +25,9 @@ impl Material for Metal {
             ray: Ray {
                 origin: hit.point,
                 direction: dir,
+                time: r.time,
             },
-            attenuate: self.albedo,
+            attenuate: self.albedo * hit.color,
         })
     }
 }
@@ -0,0 +1,472 @@
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::Range;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use nalgebra::Vector3;
+
+use types::*;
+use accumulator::Accumulator;
+use checkpoint;
+use render;
+use scenes;
+use stats::{RayStats, RenderStats};
+use tiles::Tile;
+
+// Distributed rendering. A coordinator connects to any number of
+// workers (see the rebound-worker binary), sends each of them the
+// render settings, then hands out tiles one at a time and stitches the
+// returned sample sums into its accumulation buffer.
+//
+// The protocol is line based, with binary payloads for sample data:
+//
+//   coordinator: "rebound job 1", the settings written by
+//                checkpoint::write_settings, "camera" followed by the
+//                camera's eye, look_at and up vectors and shutter
+//                interval, then "end"
+//   worker:      "ready" once the scene and sample sets are built, or
+//                "error <message>"
+//   coordinator: "tile <x0> <y0> <x1> <y1> <first sample> <end sample>"
+//   worker:      "result", then for each pixel of the tile, row by row,
+//                its sum as three little-endian f64s, then the primary
+//                ray, total ray and intersection test counts as
+//                little-endian u64s
+//   coordinator: "quit" when there are no more tiles
+//
+// Workers derive everything from the seed in the settings, so the
+// stitched image is identical to a local render with the same
+// settings. A worker that doesn't answer within the configured timeout
+// is dropped and its tile rendered by another.
+
+const JOB_HEADER: &'static str = "rebound job 1";
+
+struct WorkItem {
+    pass: usize,
+    tile_index: usize,
+    range: Range<usize>,
+}
+
+// The work items not yet rendered, and how many of them have been
+// handed out but not returned. Workers wait for more work until both
+// are exhausted, as a worker that fails puts its item back.
+struct WorkQueue {
+    items: VecDeque<WorkItem>,
+    in_progress: usize,
+}
+
+enum Message {
+    Ready,
+    Done(WorkItem, Vec<Color>, RayStats),
+    Failed(String, io::Error),
+}
+
+// Listen for coordinators on the given address and serve each
+// connection on its own thread. Scenes are built with model files from
+// models_dir, since the coordinator's paths may not exist here.
+pub fn serve(addr: &str, models_dir: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let models_dir = String::from(models_dir);
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+            if let Err(e) = serve_connection(stream, models_dir) {
+                eprintln!("Connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn serve_connection(stream: TcpStream, models_dir: String) -> io::Result<()> {
+    // See connect.
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    if read_line(&mut reader)? != JOB_HEADER {
+        return Err(protocol_error("expected job header"));
+    }
+
+    let mut config = Config::default();
+    config.models_dir = models_dir;
+    let mut core = None;
+    config.quiet = true;
+    loop {
+        let line = read_line(&mut reader)?;
+        if line == "end" {
+            break;
+        }
+        let (key, value) = checkpoint::split_setting(&line)?;
+        if key == "camera" {
+            core = Some(parse_core(value)?);
+        } else if !checkpoint::apply_setting(&mut config, key, value)? {
+            return Err(protocol_error(&format!("unknown setting: {}", key)));
+        }
+    }
+
+    let build_scene = match scenes::lookup_scene(&config.scene_name) {
+        Some(b) => b,
+        None => {
+            writeln!(writer, "error unknown scene {}", config.scene_name)?;
+            writer.flush()?;
+            return Err(protocol_error("unknown scene"));
+        },
+    };
+
+    // The coordinator's camera may have been moved, e.g. for an
+    // animation frame.
+    let mut scene = match build_scene(&config) {
+        Ok(s) => s,
+        Err(e) => {
+            writeln!(writer, "error could not build scene {}: {}", config.scene_name, e)?;
+            writer.flush()?;
+            return Err(e);
+        },
+    };
+    scene.prepare();
+    if let Some(ref name) = config.camera_name {
+        if !scene.select_camera(name) {
+            writeln!(writer, "error unknown camera {}", name)?;
+            writer.flush()?;
+            return Err(protocol_error("unknown camera"));
+        }
+    }
+    if let Some(core) = core {
+        scene.camera.set_core(core);
+        scene.autofocus();
+    }
+    let samples = MasterSampleSets::new(config.seed, config.sample_root,
+                                        config.max_depth, scene.view_plane.hres);
+    let sample_count = render::pass_ranges(&config).last().map_or(0, |r| r.end);
+
+    writeln!(writer, "ready")?;
+    writer.flush()?;
+
+    loop {
+        let line = read_line(&mut reader)?;
+        if line == "quit" {
+            return Ok(());
+        }
+
+        let fields: Vec<usize> = line.split(' ').skip(1)
+            .map(|f| f.parse().map_err(|_| protocol_error("bad tile request")))
+            .collect::<io::Result<Vec<usize>>>()?;
+        if !line.starts_with("tile ") || fields.len() != 6 {
+            return Err(protocol_error("expected tile request"));
+        }
+
+        let tile = Tile { x0: fields[0], y0: fields[1], x1: fields[2], y1: fields[3] };
+        let range = fields[4]..fields[5];
+        if tile.x0 >= tile.x1 || tile.x1 > scene.view_plane.hres ||
+            tile.y0 >= tile.y1 || tile.y1 > scene.view_plane.vres ||
+            range.start >= range.end || range.end > sample_count {
+            writeln!(writer, "error bad tile request {}", line)?;
+            writer.flush()?;
+            return Err(protocol_error("bad tile request"));
+        }
+
+        // Render the tile's rows in parallel so a worker uses all of its
+        // cores. A row is rendered exactly as it would be as part of
+        // the whole tile.
+        let rows: Vec<(Vec<Color>, RayStats)> = (tile.y0..tile.y1).into_par_iter().map(|y| {
+            let row = Tile { x0: tile.x0, y0: y, x1: tile.x1, y1: y + 1 };
+            let mut stats = RayStats::default();
+            let sums = render::render_tile(&scene, scene.camera.as_ref(), &samples,
+                                           &row, range.clone(), &mut stats);
+            (sums, stats)
+        }).collect();
+
+        let mut stats = RayStats::default();
+        writeln!(writer, "result")?;
+        for (sums, row_stats) in rows {
+            for c in sums {
+                for v in &[c.r, c.g, c.b] {
+                    writer.write_all(&v.to_bits().to_le_bytes())?;
+                }
+            }
+            stats += row_stats;
+        }
+        for n in &[stats.primary_rays, stats.total_rays, stats.intersection_tests] {
+            writer.write_all(&(*n as u64).to_le_bytes())?;
+        }
+        writer.flush()?;
+    }
+}
+
+// Render the scene on the given workers. Like render::render_from, this
+// continues from the given accumulation buffer and skips tiles that
+// already have their samples. Tile sums are added in pass order for
+// each tile, so the result doesn't depend on which worker finishes
+// first. Workers that can't be reached or that fail are reported on
+// stderr and their tiles are given to the others; the render fails
+// only if every worker fails while tiles remain.
+pub fn render_from(scene: &Scene, workers: &Vec<String>, acc: Accumulator,
+                   observer: &RenderObserver, cancel: &CancelToken)
+                   -> io::Result<(Image, RenderStats)> {
+    let mut acc = acc;
+    let mut stats = RenderStats::default();
+    let tiles = render::tiles(scene);
+    let ranges = render::pass_ranges(&scene.config);
+
+    // The first pass each tile still needs; earlier passes are already
+    // in the accumulation buffer.
+    let mut next_pass: Vec<usize> = tiles.iter().map(|t| {
+        let done = acc.samples_at(t.x0, t.y0);
+        ranges.iter().take_while(|r| r.end <= done).count()
+    }).collect();
+
+    let mut queue = VecDeque::new();
+    for (pass, range) in ranges.iter().enumerate() {
+        for tile_index in 0..tiles.len() {
+            if pass >= next_pass[tile_index] {
+                queue.push_back(WorkItem { pass, tile_index, range: range.clone() });
+            }
+        }
+    }
+    let mut remaining = queue.len();
+    let queue = Arc::new((Mutex::new(WorkQueue { items: queue, in_progress: 0 }), Condvar::new()));
+
+    observer.setting_up();
+
+    let setup_start = Instant::now();
+    let (tx, rx) = channel();
+    for addr in workers {
+        let tiles = tiles.clone();
+        let queue = queue.clone();
+        let tx = tx.clone();
+        let config = scene.config.clone();
+        let core = scene.camera.core().clone();
+        let cancel = cancel.clone();
+        let addr = addr.clone();
+
+        thread::spawn(move || {
+            let timeout = Duration::from_secs(config.worker_timeout);
+            let result = connect(&addr, timeout)
+                .and_then(|s| run_worker(s, &config, &core, &tiles, &queue, &tx, &cancel));
+            if let Err(e) = result {
+                let e = match e.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut =>
+                        io::Error::new(io::ErrorKind::TimedOut,
+                                       format!("no reply within {} s", config.worker_timeout)),
+                    _ => e,
+                };
+                let _ = tx.send(Message::Failed(addr, e));
+            }
+        });
+    }
+    drop(tx);
+
+    let mut started = false;
+    let mut pending: HashMap<(usize, usize), (Vec<Color>, usize)> = HashMap::new();
+    let mut pass_done = vec![0; ranges.len()];
+    let mut render_start = Instant::now();
+
+    for message in rx.iter() {
+        match message {
+            Message::Ready => {
+                // Rendering starts as soon as the first worker has set up.
+                if !started {
+                    started = true;
+                    stats.sample_generation = setup_start.elapsed();
+                    observer.render_started();
+                    render_start = Instant::now();
+
+                    // Report tiles that are already complete, as
+                    // render::render_from does.
+                    for (tile_index, &n) in next_pass.iter().enumerate() {
+                        for pass in 0..n {
+                            pass_done[pass] += 1;
+                            observer.tile_finished(&tiles[tile_index], &acc);
+                        }
+                    }
+                    for (pass, &done) in pass_done.iter().enumerate() {
+                        if done == tiles.len() {
+                            observer.pass_finished(pass, &acc);
+                        }
+                    }
+                }
+            },
+            Message::Failed(addr, e) => {
+                eprintln!("Worker {} failed: {}", addr, e);
+            },
+            Message::Done(item, sums, tile_stats) => {
+                stats.rays += tile_stats;
+                remaining -= 1;
+                pending.insert((item.tile_index, item.pass), (sums, item.range.len()));
+
+                // Add this tile's results in pass order.
+                let t = item.tile_index;
+                while let Some((sums, n)) = pending.remove(&(t, next_pass[t])) {
+                    acc.add_tile(&tiles[t], &sums, n);
+                    observer.tile_finished(&tiles[t], &acc);
+
+                    pass_done[next_pass[t]] += 1;
+                    if pass_done[next_pass[t]] == tiles.len() {
+                        observer.pass_finished(next_pass[t], &acc);
+                    }
+                    next_pass[t] += 1;
+                }
+            },
+        }
+    }
+    stats.rendering = render_start.elapsed();
+
+    if remaining > 0 && !cancel.is_cancelled() {
+        return Err(protocol_error("all workers failed before the render finished"));
+    }
+
+    Ok((acc.image(), stats))
+}
+
+// Connect to a worker. Reads and writes time out, so a worker that hangs
+// fails like one that disconnects and its tile is given to another.
+fn connect(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let addr = addr.to_socket_addrs()?.next().ok_or_else(|| protocol_error("no address"))?;
+    let stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    // Each side waits for the other's reply, so send writes at once
+    // rather than holding back small ones until earlier data is
+    // acknowledged.
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+fn run_worker(stream: TcpStream, config: &Config, core: &CameraCore, tiles: &Vec<Tile>,
+              queue: &(Mutex<WorkQueue>, Condvar), results: &Sender<Message>,
+              cancel: &CancelToken) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    writeln!(writer, "{}", JOB_HEADER)?;
+    checkpoint::write_settings(&mut writer, config)?;
+    writeln!(writer, "camera {} {} {} {} {} {} {} {} {} {} {}",
+             core.eye.x, core.eye.y, core.eye.z,
+             core.look_at.x, core.look_at.y, core.look_at.z,
+             core.up.x, core.up.y, core.up.z,
+             core.shutter_open, core.shutter_close)?;
+    writeln!(writer, "end")?;
+    writer.flush()?;
+
+    let reply = read_line(&mut reader)?;
+    if reply != "ready" {
+        return Err(protocol_error(&reply));
+    }
+    let _ = results.send(Message::Ready);
+
+    let (work, changed) = queue;
+    while let Some(item) = next_item(queue, cancel) {
+        match render_remote(&mut reader, &mut writer, &tiles[item.tile_index], &item.range) {
+            Ok((sums, stats)) => {
+                let _ = results.send(Message::Done(item, sums, stats));
+                work.lock().unwrap().in_progress -= 1;
+                changed.notify_all();
+            },
+            Err(e) => {
+                // Give the tile back so another worker can render it.
+                {
+                    let mut work = work.lock().unwrap();
+                    work.in_progress -= 1;
+                    work.items.push_front(item);
+                }
+                changed.notify_all();
+                return Err(e);
+            },
+        }
+    }
+
+    writeln!(writer, "quit")?;
+    writer.flush()
+}
+
+// Take the next work item, waiting while the queue is empty but other
+// workers still have items that may be put back. Returns None when all
+// of the work is done or the render is cancelled.
+fn next_item(queue: &(Mutex<WorkQueue>, Condvar), cancel: &CancelToken) -> Option<WorkItem> {
+    let (work, changed) = queue;
+    let mut work = work.lock().unwrap();
+    loop {
+        if cancel.is_cancelled() {
+            return None;
+        }
+        if let Some(item) = work.items.pop_front() {
+            work.in_progress += 1;
+            return Some(item);
+        }
+        if work.in_progress == 0 {
+            return None;
+        }
+        // Wake up now and then to notice cancellation.
+        work = changed.wait_timeout(work, Duration::from_millis(100)).unwrap().0;
+    }
+}
+
+fn render_remote(reader: &mut BufReader<TcpStream>, writer: &mut BufWriter<TcpStream>,
+                 tile: &Tile, range: &Range<usize>) -> io::Result<(Vec<Color>, RayStats)> {
+    writeln!(writer, "tile {} {} {} {} {} {}",
+             tile.x0, tile.y0, tile.x1, tile.y1, range.start, range.end)?;
+    writer.flush()?;
+
+    let reply = read_line(reader)?;
+    if reply != "result" {
+        return Err(protocol_error(&reply));
+    }
+
+    let mut sums = Vec::with_capacity(tile.pixel_count());
+    for _ in 0..tile.pixel_count() {
+        sums.push(Color::new(f64::from_bits(read_u64(reader)?),
+                             f64::from_bits(read_u64(reader)?),
+                             f64::from_bits(read_u64(reader)?)));
+    }
+
+    let stats = RayStats {
+        primary_rays: read_u64(reader)? as usize,
+        total_rays: read_u64(reader)? as usize,
+        intersection_tests: read_u64(reader)? as usize,
+    };
+
+    Ok((sums, stats))
+}
+
+fn parse_core(value: &str) -> io::Result<CameraCore> {
+    let v: Vec<f64> = value.split(' ')
+        .map(|f| f.parse().map_err(|_| protocol_error("bad camera")))
+        .collect::<io::Result<Vec<f64>>>()?;
+    if v.len() != 11 {
+        return Err(protocol_error("bad camera"));
+    }
+
+    Ok(CameraCore::new(Vector3::new(v[0], v[1], v[2]),
+                       Vector3::new(v[3], v[4], v[5]),
+                       Vector3::new(v[6], v[7], v[8]))
+       .with_shutter(v[9], v[10]))
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+    }
+    Ok(String::from(line.trim_end()))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut word = [0u8; 8];
+    reader.read_exact(&mut word)?;
+    Ok(u64::from_le_bytes(word))
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("protocol error: {}", msg))
+}
@@ -0,0 +1,91 @@
+
+use std::fs::File;
+use std::io::stdout;
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use rebound::types::*;
+use rebound::tiles::Tile;
+use rebound::accumulator::Accumulator;
+use rebound::checkpoint;
+
+// Reports render progress on the console (unless running quietly),
+// saves a checkpoint periodically if one was requested and, in
+// progressive mode, writes the running image to the output file after
+// every pass but the last, which main writes itself.
+pub struct CliObserver {
+    config: Config,
+    total_pixels: usize,
+    pixels_done: AtomicUsize,
+    last_checkpoint: Mutex<Instant>,
+}
+
+impl CliObserver {
+    pub fn new(config: &Config, total_pixels: usize) -> CliObserver {
+        CliObserver {
+            config: config.clone(),
+            total_pixels,
+            pixels_done: AtomicUsize::new(0),
+            last_checkpoint: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl RenderObserver for CliObserver {
+    fn setting_up(&self) {
+        if !self.config.quiet {
+            println!("Generating samples.");
+        }
+    }
+
+    fn render_started(&self) {
+        if !self.config.quiet {
+            println!("Rendering.");
+        }
+    }
+
+    fn tile_finished(&self, tile: &Tile, acc: &Accumulator) {
+        if let Some(ref path) = self.config.checkpoint_file {
+            let mut last = self.last_checkpoint.lock().unwrap();
+            if last.elapsed() >= Duration::from_secs(self.config.checkpoint_interval) {
+                // Keep rendering if the checkpoint can't be written, e.g.
+                // because the disk is full; the next save will try again.
+                if let Err(e) = checkpoint::save(path, &self.config, acc) {
+                    eprintln!("\nWarning: could not save checkpoint to {}: {}", path, e);
+                }
+                *last = Instant::now();
+            }
+        }
+
+        let done = self.pixels_done.fetch_add(tile.pixel_count(), Ordering::SeqCst) +
+            tile.pixel_count();
+
+        if !self.config.quiet {
+            let progress = 100.0 * (done as f64) / (self.total_pixels as f64);
+            print!("  {} %\r", progress as u32);
+            stdout().flush().unwrap();
+
+            if done == self.total_pixels {
+                println!("");
+            }
+        }
+    }
+
+    fn pass_finished(&self, pass: usize, acc: &Accumulator) {
+        self.pixels_done.store(0, Ordering::SeqCst);
+
+        if let Some(passes) = self.config.passes {
+            if pass + 1 < passes {
+                let mut output_file = File::create(self.config.output_file.clone()).unwrap();
+                self.config.output_image(acc.image()).write(&mut output_file);
+
+                if !self.config.quiet {
+                    println!("Pass {} of {} written to {}", pass + 1, passes,
+                             self.config.output_file);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+
+use rayon;
+use rayon::prelude::*;
+
+use std::ops::Range;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use types::*;
+use accumulator::Accumulator;
+use stats::{RayStats, RenderStats};
+use tiles;
+use tiles::Tile;
+
+// Render the scene as seen by the given camera. The image is split into
+// tiles according to the scene configuration; tiles are handed out to
+// the rayon thread pool in the configured order and reported to the
+// observer as they finish. If the cancellation token is triggered the
+// render stops early and returns whatever tiles were completed; the
+// rest are left black.
+//
+// Normally each pixel gets all of its samples in a single pass. If the
+// configuration asks for progressive passes, each pass instead adds one
+// sample per pixel to the accumulation buffer, and the observer is
+// given the buffer after every pass.
+//
+// All randomness is derived from the configured seed, so rendering the
+// same scene with the same configuration gives the same image.
+//
+// The returned statistics cover sample generation and rendering; the
+// output time is left for the caller to fill in.
+pub fn render(scene: &Scene, camera: &Camera, observer: &RenderObserver,
+              cancel: &CancelToken) -> (Image, RenderStats) {
+    let vp = &scene.view_plane;
+    render_from(scene, camera, Accumulator::new(vp.hres, vp.vres), observer, cancel)
+}
+
+// Continue a render from a partially filled accumulation buffer, such
+// as one loaded from a checkpoint. Tiles that already have the samples
+// a pass would add are skipped, so as long as the scene and
+// configuration are unchanged the result is identical to an
+// uninterrupted render.
+pub fn render_from(scene: &Scene, camera: &Camera, acc: Accumulator,
+                   observer: &RenderObserver, cancel: &CancelToken) -> (Image, RenderStats) {
+    let vp = &scene.view_plane;
+    let acc = Mutex::new(acc);
+    let mut stats = RenderStats::default();
+
+    observer.setting_up();
+
+    let setup_start = Instant::now();
+    let samples = MasterSampleSets::new(scene.config.seed, scene.config.sample_root,
+                                        scene.config.max_depth, vp.hres);
+    let tiles = tiles(scene);
+    stats.sample_generation = setup_start.elapsed();
+
+    observer.render_started();
+
+    let render_start = Instant::now();
+    for (pass, range) in pass_ranges(&scene.config).into_iter().enumerate() {
+        stats.rays += render_pass(scene, camera, &samples, &tiles, range, &acc,
+                                  observer, cancel);
+
+        if cancel.is_cancelled() {
+            break;
+        }
+
+        observer.pass_finished(pass, &acc.lock().unwrap());
+    }
+    stats.rendering = render_start.elapsed();
+
+    (acc.into_inner().unwrap().image(), stats)
+}
+
+// The part of the image to render: the configured region, or the
+// whole image. Panics if the region doesn't fit; see check_region.
+pub fn region(scene: &Scene) -> Tile {
+    let vp = &scene.view_plane;
+    if let Err(e) = check_region(scene) {
+        panic!("{}", e);
+    }
+    scene.config.region.unwrap_or(Tile { x0: 0, y0: 0, x1: vp.hres, y1: vp.vres })
+}
+
+// Check that the configured region, if any, is a non-empty part of the
+// scene's image.
+pub fn check_region(scene: &Scene) -> Result<(), String> {
+    let vp = &scene.view_plane;
+    match scene.config.region {
+        Some(r) if r.x0 >= r.x1 || r.y0 >= r.y1 || r.x1 > vp.hres || r.y1 > vp.vres =>
+            Err(format!("Region {},{} to {},{} is empty or outside the {}x{} image",
+                        r.x0, r.y0, r.x1, r.y1, vp.hres, vp.vres)),
+        _ => Ok(()),
+    }
+}
+
+// The tiles to render, in the configured order.
+pub fn tiles(scene: &Scene) -> Vec<Tile> {
+    tiles::tiles_in(&region(scene), scene.config.tile_size, scene.config.tile_order)
+}
+
+// The sample numbers rendered in each pass: all of a pixel's samples
+// in one pass, or one sample per pass in progressive mode.
+pub fn pass_ranges(config: &Config) -> Vec<Range<usize>> {
+    match config.passes {
+        Some(n) => (0..n).map(|k| k..(k + 1)).collect(),
+        None => vec![0..(config.sample_root * config.sample_root)],
+    }
+}
+
+// Render samples in the given range for every tile, adding them to the
+// accumulation buffer.
+fn render_pass(scene: &Scene, camera: &Camera, samples: &MasterSampleSets,
+               tiles: &Vec<Tile>, range: Range<usize>, acc: &Mutex<Accumulator>,
+               observer: &RenderObserver, cancel: &CancelToken) -> RayStats {
+    // Each worker repeatedly claims the next unrendered tile, so tiles
+    // are started in order no matter how long each one takes.
+    let next_tile = AtomicUsize::new(0);
+    let worker_stats: Vec<RayStats> = (0..rayon::current_num_threads()).into_par_iter().map(|_| {
+        let mut local_stats = RayStats::default();
+
+        loop {
+            let i = next_tile.fetch_add(1, Ordering::SeqCst);
+            if i >= tiles.len() || cancel.is_cancelled() {
+                break;
+            }
+
+            // Tiles restored from a checkpoint are still reported so
+            // observers see every tile of every pass.
+            let tile = &tiles[i];
+            {
+                let acc = acc.lock().unwrap();
+                if acc.samples_at(tile.x0, tile.y0) >= range.end {
+                    observer.tile_finished(tile, &acc);
+                    continue;
+                }
+            }
+
+            let sums = render_tile(scene, camera, samples, tile, range.clone(), &mut local_stats);
+            let mut acc = acc.lock().unwrap();
+            acc.add_tile(tile, &sums, range.len());
+            observer.tile_finished(tile, &acc);
+        }
+
+        local_stats
+    }).collect();
+
+    let mut stats = RayStats::default();
+    for s in worker_stats {
+        stats += s;
+    }
+    stats
+}
+
+// Render the samples in the given range for each pixel of a tile and
+// return their sums, row by row. The result depends only on the scene,
+// the sample sets and the pixels' positions, never on which thread or
+// machine renders them or how a tile was clipped to a region.
+pub fn render_tile(scene: &Scene, camera: &Camera, samples: &MasterSampleSets,
+                   tile: &Tile, range: Range<usize>, stats: &mut RayStats) -> Vec<Color> {
+    let mut sums = Vec::with_capacity(tile.pixel_count());
+
+    // Shuffle sample sets as for the unclipped tile.
+    let grid_x0 = tile.x0 - tile.x0 % scene.config.tile_size;
+
+    for row in tile.y0..tile.y1 {
+        let keys = [range.start as u64, row as u64, grid_x0 as u64];
+        let sample_set_indexes = samples.shuffle_indices(&keys, tile.x1 - grid_x0);
+        for col in tile.x0..tile.x1 {
+            sums.push(render_pixel(scene, camera, samples, sample_set_indexes[col - grid_x0],
+                                   col, row, range.clone(), stats));
+        }
+    }
+
+    sums
+}
+
+// Trace the pixel's samples in the given range and return their sum.
+// Sample numbers past the end of the pixel's sample set wrap around.
+fn render_pixel(scene: &Scene, camera: &Camera, samples: &MasterSampleSets,
+                set_index: usize, col: usize, row: usize, range: Range<usize>,
+                stats: &mut RayStats) -> Color {
+    let vp = &scene.view_plane;
+    let half_img_h = vp.vres as f64 * 0.5;
+    let half_img_w = vp.hres as f64 * 0.5;
+    let pixel_samples = &samples.pixel_sets[set_index % samples.pixel_sets.len()];
+    let lens_samples = &samples.lens_sets[set_index % samples.lens_sets.len()];
+    let time_samples = &samples.time_sets[set_index % samples.time_sets.len()];
+    let mut color = black();
+
+    for k in range {
+        let index = k % pixel_samples.len();
+        let point = &pixel_samples[index];
+        let s = CameraSample {
+            px: col as f64 - half_img_w + point.x,
+            py: (vp.vres - row) as f64 - half_img_h + point.y,
+            lx: lens_samples[index].x,
+            ly: lens_samples[index].y,
+            t: time_samples[index],
+        };
+        color += camera.sample_color(vp, &s, &mut |r| {
+            stats.primary_rays += 1;
+            scene.color(r, index, &samples.hemi_sets[set_index], 0, stats)
+        });
+    }
+
+    color
+}
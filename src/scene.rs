@@ -2,25 +2,126 @@
 extern crate nalgebra;
 use nalgebra::{Vector3};
 
+use std::mem;
+use std::time::{Duration, Instant};
+
 use types::*;
+use constants::DEFAULT_CAMERA_NAME;
+use stats::RayStats;
+use bvh::{Aabb, Bvh};
 
 impl Scene {
-    fn hit<'a>(&'a self, r: &Ray) -> Option<Hit<'a>> {
-        self.objects.iter()
-            .filter_map(|o| o.hit(r))
-            .min_by(Hit::compare)
+    // A scene with a single camera, named DEFAULT_CAMERA_NAME; add
+    // others with with_camera.
+    pub fn new(objects: Vec<Box<Intersectable>>, background: Color, camera: Box<Camera>,
+               config: &Config, view_plane: ViewPlane) -> Scene {
+        Scene {
+            objects,
+            background,
+            camera,
+            camera_name: String::from(DEFAULT_CAMERA_NAME),
+            cameras: vec![],
+            bvh: None,
+            config: config.clone(),
+            view_plane,
+            warnings: vec![],
+        }
+    }
+
+    pub fn hit<'a>(&'a self, r: &Ray, stats: &mut RayStats) -> Option<Hit<'a>> {
+        match self.bvh {
+            Some(ref bvh) => bvh.hit(r, |i, t_max| {
+                self.objects[i].hit_with_stats(r, stats)
+                    .filter(|h| h.distance < t_max)
+                    .map(|h| (h.distance, h))
+            }),
+            None => {
+                self.objects.iter()
+                    .filter_map(|o| o.hit_with_stats(r, stats))
+                    .min_by(Hit::compare)
+            },
+        }
+    }
+
+    // Get a newly built scene ready to render: build its BVH, then
+    // autofocus its camera. Returns the time the BVH took to build.
+    pub fn prepare(&mut self) -> Duration {
+        let bvh_start = Instant::now();
+        self.build_bvh();
+        let bvh_build = bvh_start.elapsed();
+        self.autofocus();
+        bvh_build
+    }
+
+    // Build the objects' own hierarchies, such as those over mesh
+    // triangles, then one over the objects. Call this once the objects
+    // are in place.
+    pub fn build_bvh(&mut self) {
+        for o in self.objects.iter_mut() {
+            o.build_bvh();
+        }
+        let bounds: Vec<Option<Aabb>> = self.objects.iter().map(|o| o.bounds()).collect();
+        self.bvh = Some(Bvh::build(&bounds));
+    }
+
+    // Focus the camera on whatever is visible at its focus point, if it
+    // autofocuses. prepare and select_camera call this, but it must be
+    // called again after moving the camera. If nothing is hit, the
+    // camera's focus is left alone.
+    pub fn autofocus(&mut self) {
+        let r = match self.camera.focus_ray(&self.view_plane) {
+            Some(r) => r,
+            None => return,
+        };
+
+        let distance = self.hit(&r, &mut RayStats::default()).map(|h| h.distance);
+        if let Some(d) = distance {
+            self.camera.focus_on(&r, d);
+        }
+    }
+
+    pub fn with_camera(mut self, name: &str, camera: Box<Camera>) -> Scene {
+        self.cameras.push((String::from(name), camera));
+        self
+    }
+
+    // The names of all the scene's cameras, the current one first.
+    pub fn camera_names(&self) -> Vec<String> {
+        let mut names = vec![self.camera_name.clone()];
+        names.extend(self.cameras.iter().map(|c| c.0.clone()));
+        names
+    }
+
+    // Make the named camera the current one, and autofocus it. Returns
+    // false if the scene has no camera by that name.
+    pub fn select_camera(&mut self, name: &str) -> bool {
+        if name == self.camera_name {
+            return true;
+        }
+
+        match self.cameras.iter().position(|c| c.0 == name) {
+            Some(i) => {
+                mem::swap(&mut self.camera_name, &mut self.cameras[i].0);
+                mem::swap(&mut self.camera, &mut self.cameras[i].1);
+                self.autofocus();
+                true
+            },
+            None => false,
+        }
     }
 }
 
 impl Scene {
-    pub fn color(&self, r: &Ray, sn: usize, ss: &Vec<Vec<Vector3<f64>>>, depth: usize) -> Color {
-        match self.hit(r) {
+    pub fn color(&self, r: &Ray, sn: usize, ss: &Vec<Vec<Vector3<f64>>>, depth: usize,
+                 stats: &mut RayStats) -> Color {
+        stats.total_rays += 1;
+        match self.hit(r, stats) {
             None => self.background,
             Some(h) => {
                 let emitted = h.material.emitted();
                 if depth < self.config.max_depth {
                     if let Some(sr) = h.material.scatter(r, &h, &ss[depth][sn]) {
-                        emitted + self.color(&sr.ray, sn, &ss, depth + 1) * sr.attenuate
+                        emitted + self.color(&sr.ray, sn, &ss, depth + 1, stats) * sr.attenuate
                     } else {
                         emitted
                     }
@@ -1,27 +1,57 @@
 
 extern crate nalgebra;
-use nalgebra::{Point3, Vector3};
+use nalgebra::{Point3, Vector2, Vector3};
+
+use std::f64::consts::PI;
+use std::io;
+use std::path::Path;
 
 use types::*;
 use cameras;
 use shapes::sphere;
 use shapes::plane;
+use shapes::triangle;
+use shapes::cuboid;
 use materials::lambertian;
 use materials::emissive;
 use materials::metal;
+use constants::*;
+use aperture::Aperture;
+use loaders::obj;
+use loaders::ply;
 
-pub fn lookup_scene(name: &String) -> Option<&Fn(&Config) -> Scene> {
-    let scenes: Vec<(String, &Fn(&Config) -> Scene)> = vec![
-        (String::from("thinlens1"), &build_scene1),
-        (String::from("thinlens2"), &build_scene2),
-    ];
+// Scenes built from model files fail if the files can't be loaded.
+fn all_scenes() -> Vec<(&'static str, &'static Fn(&Config) -> io::Result<Scene>)> {
+    vec![
+        ("thinlens1", &build_scene1),
+        ("thinlens2", &build_thinlens2),
+        ("ortho1", &build_ortho1),
+        ("fisheye1", &build_fisheye1),
+        ("panorama1", &build_panorama1),
+        ("stereo1", &build_stereo1),
+        ("anaglyph1", &build_anaglyph1),
+        ("motionblur1", &build_motionblur1),
+        ("lens1", &build_lens1),
+        ("autofocus1", &build_autofocus1),
+        ("bokeh1", &build_bokeh1),
+        ("mesh1", &build_mesh1),
+        ("obj1", &build_obj1),
+        ("ply1", &build_ply1),
+        ("room1", &build_room1),
+    ]
+}
+
+pub fn scene_names() -> Vec<&'static str> {
+    all_scenes().iter().map(|t| t.0).collect()
+}
 
-    scenes.iter()
-        .find(|t| t.0.eq(name))
+pub fn lookup_scene(name: &String) -> Option<&Fn(&Config) -> io::Result<Scene>> {
+    all_scenes().into_iter()
+        .find(|t| t.0 == name)
         .map(|t| t.1)
 }
 
-fn build_scene1(config: &Config) -> Scene {
+fn build_scene1(config: &Config) -> io::Result<Scene> {
     let mut ss = (-10..10).map(|z|
         Box::new(sphere::Sphere {
             center: Vector3::new(0.0, 0.5, z as f64),
@@ -57,6 +87,8 @@ fn build_scene1(config: &Config) -> Scene {
         zoom_factor: 1.0,
         focal_plane_distance: 4.0,
         lens_radius: 0.05,
+        aperture: Aperture::Circle,
+        autofocus: None,
     };
 
     let mut all_objects: Vec<Box<Intersectable>> = vec![
@@ -66,17 +98,13 @@ fn build_scene1(config: &Config) -> Scene {
 
     all_objects.append(&mut ss);
 
-    Scene {
-        objects: all_objects,
-        background: Color::all(0.5),
-        camera: Box::new(cam),
-        config: config.clone(),
-        view_plane: ViewPlane {
-            hres: 800,
-            vres: 400,
-            pixel_size: 1.0,
-        },
-    }
+    let view_plane = ViewPlane {
+        hres: 800,
+        vres: 400,
+        pixel_size: 1.0,
+    };
+
+    Ok(Scene::new(all_objects, Color::all(0.5), Box::new(cam), config, view_plane))
 }
 
 fn build_scene2(config: &Config) -> Scene {
@@ -150,6 +178,8 @@ fn build_scene2(config: &Config) -> Scene {
         zoom_factor: 1.0,
         focal_plane_distance: 4.0,
         lens_radius: 0.0,
+        aperture: Aperture::Circle,
+        autofocus: None,
     };
 
     let all_objects: Vec<Box<Intersectable>> = vec![
@@ -162,15 +192,617 @@ fn build_scene2(config: &Config) -> Scene {
         Box::new(s_back_right),
     ];
 
-    Scene {
-        objects: all_objects,
-        background: Color::all(0.0),
-        camera: Box::new(cam),
-        config: config.clone(),
-        view_plane: ViewPlane {
-            hres: 2880,
-            vres: 1800,
-            pixel_size: 0.25,
-        },
+    let view_plane = ViewPlane {
+        hres: 2880,
+        vres: 1800,
+        pixel_size: 0.25,
+    };
+
+    Scene::new(all_objects, Color::all(0.0), Box::new(cam), config, view_plane)
+}
+
+// The thinlens2 scene, which has a view from above and a close-up of
+// the front left sphere besides its main camera.
+fn build_thinlens2(config: &Config) -> io::Result<Scene> {
+    let scene = build_scene2(config);
+
+    let top = cameras::OrthographicCamera::with_height(
+        CameraCore::new(
+            Vector3::new(0.0, 6.0, 8.0),
+            Vector3::new(0.0, 0.5, -1.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            ),
+        &scene.view_plane, 3.75);
+
+    let detail = cameras::ThinLensCamera::with_fov(
+        CameraCore::new(
+            Vector3::new(-0.5, 1.0, 3.0),
+            Vector3::new(-2.0, 0.5, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            ),
+        &scene.view_plane, 25.0, 0.02, 3.0)
+        .with_autofocus();
+
+    Ok(scene
+        .with_camera("top", Box::new(top))
+        .with_camera("detail", Box::new(detail)))
+}
+
+// The thinlens2 scene viewed from above with an orthographic camera.
+fn build_ortho1(config: &Config) -> io::Result<Scene> {
+    let mut scene = build_scene2(config);
+
+    scene.camera = Box::new(cameras::OrthographicCamera {
+        core: CameraCore::new(
+                  Vector3::new(0.0, 6.0, 8.0),
+                  Vector3::new(0.0, 0.5, -1.0),
+                  Vector3::new(0.0, 1.0, 0.0),
+                  ),
+        zoom_factor: 1.0,
+    });
+    scene.view_plane = ViewPlane {
+        hres: 800,
+        vres: 500,
+        pixel_size: 0.0075,
+    };
+
+    Ok(scene)
+}
+
+// The thinlens2 scene through a 180 degree fisheye lens.
+fn build_fisheye1(config: &Config) -> io::Result<Scene> {
+    let mut scene = build_scene2(config);
+
+    scene.camera = Box::new(cameras::FisheyeCamera {
+        core: CameraCore::new(
+                  Vector3::new(0.0, 1.5, 4.0),
+                  Vector3::new(0.0, 0.5, -1.0),
+                  Vector3::new(0.0, 1.0, 0.0),
+                  ),
+        fov: 180.0,
+    });
+    scene.view_plane = ViewPlane {
+        hres: 600,
+        vres: 600,
+        pixel_size: 1.0,
+    };
+
+    Ok(scene)
+}
+
+// A 360 degree equirectangular panorama from among the spheres of the
+// thinlens2 scene, suitable for use as an environment map.
+fn build_panorama1(config: &Config) -> io::Result<Scene> {
+    let mut scene = build_scene2(config);
+
+    scene.camera = Box::new(cameras::SphericalCamera {
+        core: CameraCore::new(
+                  Vector3::new(0.0, 1.0, -1.0),
+                  Vector3::new(0.0, 1.0, -2.0),
+                  Vector3::new(0.0, 1.0, 0.0),
+                  ),
+        horizontal_fov: 360.0,
+        vertical_fov: 180.0,
+    });
+    scene.view_plane = ViewPlane {
+        hres: 1000,
+        vres: 500,
+        pixel_size: 1.0,
+    };
+
+    Ok(scene)
+}
+
+fn stereo_scene2(config: &Config, output: cameras::StereoOutput, hres: usize) -> Scene {
+    let mut scene = build_scene2(config);
+
+    let center = cameras::ThinLensCamera {
+        core: CameraCore::new(
+                  Vector3::new(0.0, 1.5, 8.0),
+                  Vector3::new(0.0, 0.6, -1.0),
+                  Vector3::new(0.0, 1.0, 0.0),
+                  ),
+        vp_distance: 700.0,
+        zoom_factor: 1.0,
+        focal_plane_distance: 4.0,
+        lens_radius: 0.0,
+        aperture: Aperture::Circle,
+        autofocus: None,
+    };
+
+    scene.camera = Box::new(cameras::StereoCamera::new(
+            center, 0.3, cameras::Convergence::OffAxis, output));
+    scene.view_plane = ViewPlane {
+        hres,
+        vres: 500,
+        pixel_size: 1.0,
+    };
+
+    scene
+}
+
+// The thinlens2 scene as a side-by-side stereo pair.
+fn build_stereo1(config: &Config) -> io::Result<Scene> {
+    Ok(stereo_scene2(config, cameras::StereoOutput::SideBySide, 1600))
+}
+
+// The thinlens2 scene as a red/cyan anaglyph.
+fn build_anaglyph1(config: &Config) -> io::Result<Scene> {
+    Ok(stereo_scene2(config, cameras::StereoOutput::Anaglyph, 800))
+}
+
+// The thinlens2 scene with two spheres moving while the shutter is
+// open. Use a sample root above 1 to see the blur.
+fn build_motionblur1(config: &Config) -> io::Result<Scene> {
+    let mut scene = build_scene2(config);
+
+    let s_sliding = sphere::MovingSphere {
+        start_center: Vector3::new(-0.6, 0.4, 1.0),
+        end_center: Vector3::new(0.6, 0.4, 1.0),
+        start_time: 0.0,
+        end_time: 1.0,
+        radius: 0.4,
+        material: Box::new(lambertian::Lambertian {
+            albedo: Color::new(0.9, 0.9, 0.9),
+        }),
+    };
+
+    let s_rising = sphere::MovingSphere {
+        start_center: Vector3::new(0.0, 0.5, -1.0),
+        end_center: Vector3::new(0.0, 1.5, -1.0),
+        start_time: 0.0,
+        end_time: 1.0,
+        radius: 0.5,
+        material: Box::new(metal::Metal {
+            albedo: Color::all(1.0),
+            gloss: 0.1,
+        }),
+    };
+
+    scene.objects.push(Box::new(s_sliding));
+    scene.objects.push(Box::new(s_rising));
+
+    scene.camera = Box::new(cameras::ThinLensCamera {
+        core: CameraCore::new(
+                  Vector3::new(0.0, 1.1, 15.0),
+                  Vector3::new(0.0, 0.5, -1.0),
+                  Vector3::new(0.0, 1.0, 0.0),
+                  ).with_shutter(0.0, 1.0),
+        vp_distance: 1500.0,
+        zoom_factor: 1.0,
+        focal_plane_distance: 4.0,
+        lens_radius: 0.0,
+        aperture: Aperture::Circle,
+        autofocus: None,
+    });
+    scene.view_plane = ViewPlane {
+        hres: 800,
+        vres: 500,
+        pixel_size: 1.0,
+    };
+
+    Ok(scene)
+}
+
+// The thinlens2 scene through a 70mm lens on a full frame sensor at
+// f/8, focused 15 units away at the middle of the scene.
+fn build_lens1(config: &Config) -> io::Result<Scene> {
+    let mut scene = build_scene2(config);
+
+    scene.view_plane = ViewPlane {
+        hres: 800,
+        vres: 500,
+        pixel_size: 1.0,
+    };
+    scene.camera = Box::new(cameras::ThinLensCamera::with_lens(
+        CameraCore::new(
+            Vector3::new(0.0, 1.1, 15.0),
+            Vector3::new(0.0, 0.5, -1.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            ),
+        &scene.view_plane, 70.0, FULL_FRAME_SENSOR_HEIGHT, 8.0, 15.0));
+
+    Ok(scene)
+}
+
+// The thinlens1 scene with a wider aperture, focused on the third
+// sphere from the front.
+fn build_autofocus1(config: &Config) -> io::Result<Scene> {
+    let mut scene = build_scene1(config)?;
+
+    scene.camera = Box::new(cameras::ThinLensCamera {
+        core: CameraCore::new(
+                  Vector3::new(2.0, 0.5, 10.0),
+                  Vector3::new(0.0, 0.0, -1.0),
+                  Vector3::new(0.0, 1.0, 0.0),
+                  ),
+        vp_distance: 400.0,
+        zoom_factor: 1.0,
+        focal_plane_distance: 4.0,
+        lens_radius: 0.1,
+        aperture: Aperture::Circle,
+        autofocus: None,
+    }.with_focus_point(cameras::FocusPoint::Pixel(245, 185)));
+
+    Ok(scene)
+}
+
+// Small, very bright distant lights far out of focus behind a sphere,
+// seen through a six-bladed aperture so that they blur into hexagons.
+fn build_bokeh1(config: &Config) -> io::Result<Scene> {
+    let mut lights: Vec<Box<Intersectable>> = (0..12).map(|i| {
+        let x = (i % 6) as f64 * 6.0 - 15.0;
+        let y = (i / 6) as f64 * 7.0 - 2.0 + (i % 2) as f64 * 2.0;
+        Box::new(sphere::Sphere {
+            center: Vector3::new(x, y, -30.0),
+            radius: 0.15,
+            material: Box::new(emissive::Emissive {
+                color: if i % 3 == 0 { Color::new(40.0, 30.0, 15.0) } else { Color::all(40.0) },
+            }),
+        }) as Box<Intersectable>
+    }).collect();
+
+    let s_subject = sphere::Sphere {
+        center: Vector3::new(0.0, 1.0, 0.0),
+        radius: 1.0,
+        material: Box::new(lambertian::Lambertian {
+            albedo: Color::new(0.9, 0.6, 0.4),
+        }),
+    };
+
+    let s_light = sphere::Sphere {
+        center: Vector3::new(0.0, 30.0, 10.0),
+        radius: 15.0,
+        material: Box::new(emissive::Emissive {
+            color: Color::all(0.6),
+        }),
+    };
+
+    let mut all_objects: Vec<Box<Intersectable>> = vec![
+        Box::new(s_subject),
+        Box::new(s_light),
+    ];
+    all_objects.append(&mut lights);
+
+    let view_plane = ViewPlane {
+        hres: 800,
+        vres: 500,
+        pixel_size: 1.0,
+    };
+
+    let cam = cameras::ThinLensCamera::with_fov(
+        CameraCore::new(
+            Vector3::new(0.0, 1.0, 6.0),
+            Vector3::new(0.0, 1.5, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            ),
+        &view_plane, 40.0, 0.15, 6.0)
+        .with_aperture(Aperture::polygon(6, 0.0))
+        .with_autofocus();
+
+    Ok(Scene::new(all_objects, Color::all(0.0), Box::new(cam), config, view_plane))
+}
+
+// A torus around the y axis made of rings x segments quads, each split
+// into two triangles, without normals.
+fn torus_mesh(center: Vector3<f64>, major_radius: f64, minor_radius: f64,
+              rings: usize, segments: usize, material: Box<Material>) -> triangle::TriangleMesh {
+    let mut positions = vec![];
+    let mut uvs = vec![];
+    for i in 0..rings {
+        let theta = 2.0 * PI * i as f64 / rings as f64;
+        for j in 0..segments {
+            let phi = 2.0 * PI * j as f64 / segments as f64;
+            let r = major_radius + minor_radius * phi.cos();
+            positions.push(center + Vector3::new(r * theta.cos(),
+                                                 minor_radius * phi.sin(),
+                                                 r * theta.sin()));
+            uvs.push(Vector2::new(i as f64 / rings as f64, j as f64 / segments as f64));
+        }
+    }
+
+    let vertex = |i: usize, j: usize| (i % rings) * segments + j % segments;
+    let mut indices = vec![];
+    for i in 0..rings {
+        for j in 0..segments {
+            indices.push([vertex(i, j), vertex(i, j + 1), vertex(i + 1, j + 1)]);
+            indices.push([vertex(i, j), vertex(i + 1, j + 1), vertex(i + 1, j)]);
+        }
+    }
+
+    triangle::TriangleMesh::new(positions, vec![], uvs, indices, material)
+}
+
+// Two low-polygon tori, flat shaded on the left and smooth shaded on
+// the right, in front of a mirror triangle.
+fn build_mesh1(config: &Config) -> io::Result<Scene> {
+    let flat = torus_mesh(Vector3::new(-1.3, 0.4, 0.0), 0.8, 0.4, 24, 12,
+                          Box::new(lambertian::Lambertian {
+                              albedo: Color::new(0.9, 0.5, 0.3),
+                          }));
+
+    let mut smooth = torus_mesh(Vector3::new(1.3, 0.4, 0.0), 0.8, 0.4, 24, 12,
+                                Box::new(lambertian::Lambertian {
+                                    albedo: Color::new(0.3, 0.5, 0.9),
+                                }));
+    smooth.compute_normals();
+
+    let mirror = triangle::Triangle {
+        v0: Vector3::new(-4.0, 0.0, -3.0),
+        v1: Vector3::new(4.0, 0.0, -3.0),
+        v2: Vector3::new(0.0, 4.0, -3.5),
+        material: Box::new(metal::Metal {
+            albedo: Color::all(0.9),
+            gloss: 0.05,
+        }),
+    };
+
+    let s_light = sphere::Sphere {
+        center: Vector3::new(0.0, 20.0, 8.0),
+        radius: 10.0,
+        material: Box::new(emissive::Emissive {
+            color: Color::all(1.0),
+        }),
+    };
+
+    let s_ground = plane::Plane {
+        origin: Point3::new(0.0, 0.0, 0.0),
+        normal: Vector3::new(0.0, 1.0, 0.0),
+        material: Box::new(lambertian::Lambertian {
+            albedo: Color::all(0.5),
+        }),
+    };
+
+    let view_plane = ViewPlane {
+        hres: 800,
+        vres: 500,
+        pixel_size: 1.0,
+    };
+
+    let cam = cameras::PinholeCamera::with_fov(
+        CameraCore::new(
+            Vector3::new(0.0, 2.5, 6.0),
+            Vector3::new(0.0, 0.6, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            ),
+        &view_plane, 45.0);
+
+    let all_objects: Vec<Box<Intersectable>> = vec![
+        Box::new(s_ground),
+        Box::new(s_light),
+        Box::new(mirror),
+        Box::new(flat),
+        Box::new(smooth),
+    ];
+
+    Ok(Scene::new(all_objects, Color::all(0.1), Box::new(cam), config, view_plane))
+}
+
+// The path of a model file in the configured models directory.
+fn model_path(config: &Config, file: &str) -> String {
+    Path::new(&config.models_dir).join(file).to_string_lossy().into_owned()
+}
+
+// The models and materials of gallery.obj on a ground plane.
+fn build_obj1(config: &Config) -> io::Result<Scene> {
+    let path = model_path(config, "gallery.obj");
+    let default_material = || Box::new(lambertian::Lambertian {
+        albedo: Color::all(0.8),
+    }) as Box<Material>;
+    let model = obj::load_obj(&path, &default_material)?;
+
+    let s_ground = plane::Plane {
+        origin: Point3::new(0.0, 0.0, 0.0),
+        normal: Vector3::new(0.0, 1.0, 0.0),
+        material: Box::new(lambertian::Lambertian {
+            albedo: Color::all(0.5),
+        }),
+    };
+
+    let mut all_objects: Vec<Box<Intersectable>> = vec![Box::new(s_ground)];
+    for mut mesh in model.meshes {
+        mesh.place(1.5, Vector3::new(0.0, 0.0, 0.0));
+        all_objects.push(Box::new(mesh));
+    }
+
+    let view_plane = ViewPlane {
+        hres: 600,
+        vres: 600,
+        pixel_size: 1.0,
+    };
+
+    let cam = cameras::PinholeCamera::with_fov(
+        CameraCore::new(
+            Vector3::new(2.5, 2.5, 5.0),
+            Vector3::new(0.0, 1.8, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            ),
+        &view_plane, 40.0);
+
+    let mut scene = Scene::new(all_objects, Color::all(0.3), Box::new(cam), config, view_plane);
+    scene.warnings = model.warnings;
+    Ok(scene)
+}
+
+// The vertex-colored icosphere in icosphere.ply, smooth shaded with its
+// own normals on the right and flat shaded on the left.
+fn build_ply1(config: &Config) -> io::Result<Scene> {
+    let path = model_path(config, "icosphere.ply");
+    let white = || Box::new(lambertian::Lambertian {
+        albedo: Color::all(1.0),
+    });
+
+    let mut smooth = ply::load_ply(&path, white())?;
+    smooth.place(1.0, Vector3::new(1.2, 1.0, 0.0));
+
+    let mut flat = ply::load_ply(&path, white())?;
+    flat.normals.clear();
+    flat.place(1.0, Vector3::new(-1.2, 1.0, 0.0));
+
+    let s_light = sphere::Sphere {
+        center: Vector3::new(0.0, 20.0, 8.0),
+        radius: 10.0,
+        material: Box::new(emissive::Emissive {
+            color: Color::all(1.5),
+        }),
+    };
+
+    let s_ground = plane::Plane {
+        origin: Point3::new(0.0, 0.0, 0.0),
+        normal: Vector3::new(0.0, 1.0, 0.0),
+        material: Box::new(lambertian::Lambertian {
+            albedo: Color::all(0.5),
+        }),
+    };
+
+    let view_plane = ViewPlane {
+        hres: 800,
+        vres: 500,
+        pixel_size: 1.0,
+    };
+
+    let cam = cameras::PinholeCamera::with_fov(
+        CameraCore::new(
+            Vector3::new(0.0, 2.0, 6.0),
+            Vector3::new(0.0, 0.9, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            ),
+        &view_plane, 40.0);
+
+    let all_objects: Vec<Box<Intersectable>> = vec![
+        Box::new(s_ground),
+        Box::new(s_light),
+        Box::new(smooth),
+        Box::new(flat),
+    ];
+
+    Ok(Scene::new(all_objects, Color::all(0.3), Box::new(cam), config, view_plane))
+}
+
+// A room with colored walls, lit by a rectangular light in the
+// ceiling, with a table, a mirror disk on the back wall and a rug.
+fn build_room1(config: &Config) -> io::Result<Scene> {
+    let diffuse = |albedo: Color| Box::new(lambertian::Lambertian { albedo }) as Box<Material>;
+    let wall = |corner: Vector3<f64>, edge1: Vector3<f64>, edge2: Vector3<f64>, albedo: Color|
+        Box::new(plane::Rectangle {
+            corner,
+            edge1,
+            edge2,
+            material: diffuse(albedo),
+        }) as Box<Intersectable>;
+
+    // The room spans x from -3 to 3, y from 0 to 4 and z from -4 to 4.
+    let mut all_objects: Vec<Box<Intersectable>> = vec![
+        // Floor, ceiling and back, front, left and right walls.
+        wall(Vector3::new(-3.0, 0.0, -4.0), Vector3::new(6.0, 0.0, 0.0),
+             Vector3::new(0.0, 0.0, 8.0), Color::all(0.7)),
+        wall(Vector3::new(-3.0, 4.0, -4.0), Vector3::new(6.0, 0.0, 0.0),
+             Vector3::new(0.0, 0.0, 8.0), Color::all(0.8)),
+        wall(Vector3::new(-3.0, 0.0, -4.0), Vector3::new(6.0, 0.0, 0.0),
+             Vector3::new(0.0, 4.0, 0.0), Color::all(0.8)),
+        wall(Vector3::new(-3.0, 0.0, 4.0), Vector3::new(6.0, 0.0, 0.0),
+             Vector3::new(0.0, 4.0, 0.0), Color::all(0.8)),
+        wall(Vector3::new(-3.0, 0.0, -4.0), Vector3::new(0.0, 0.0, 8.0),
+             Vector3::new(0.0, 4.0, 0.0), Color::new(0.7, 0.2, 0.2)),
+        wall(Vector3::new(3.0, 0.0, -4.0), Vector3::new(0.0, 0.0, 8.0),
+             Vector3::new(0.0, 4.0, 0.0), Color::new(0.2, 0.6, 0.3)),
+    ];
+
+    let light = plane::Rectangle {
+        corner: Vector3::new(-1.0, 3.99, -1.5),
+        edge1: Vector3::new(2.0, 0.0, 0.0),
+        edge2: Vector3::new(0.0, 0.0, 2.0),
+        material: Box::new(emissive::Emissive {
+            color: Color::all(6.0),
+        }),
+    };
+    all_objects.push(Box::new(light));
+
+    // A table top on four legs.
+    let wood = Color::new(0.6, 0.4, 0.2);
+    all_objects.push(Box::new(cuboid::Cuboid::new(
+        Vector3::new(-1.2, 1.0, -1.6),
+        Vector3::new(1.2, 1.1, -0.4),
+        diffuse(wood))));
+    for &(x, z) in [(-1.1, -1.5), (1.0, -1.5), (-1.1, -0.6), (1.0, -0.6)].iter() {
+        all_objects.push(Box::new(cuboid::Cuboid::new(
+            Vector3::new(x, 0.0, z),
+            Vector3::new(x + 0.1, 1.0, z + 0.1),
+            diffuse(wood))));
+    }
+
+    all_objects.push(Box::new(cuboid::Cuboid::new(
+        Vector3::new(-0.8, 1.1, -1.3),
+        Vector3::new(-0.3, 1.6, -0.8),
+        diffuse(Color::new(0.3, 0.4, 0.8)))));
+    all_objects.push(Box::new(sphere::Sphere {
+        center: Vector3::new(0.5, 1.4, -1.0),
+        radius: 0.3,
+        material: Box::new(metal::Metal {
+            albedo: Color::all(0.9),
+            gloss: 0.0,
+        }),
+    }));
+
+    all_objects.push(Box::new(plane::Disk {
+        center: Vector3::new(0.0, 2.3, -3.99),
+        normal: Vector3::new(0.0, 0.0, 1.0),
+        radius: 0.9,
+        material: Box::new(metal::Metal {
+            albedo: Color::all(0.9),
+            gloss: 0.02,
+        }),
+    }));
+    all_objects.push(Box::new(plane::Disk {
+        center: Vector3::new(0.0, 0.01, 1.0),
+        normal: Vector3::new(0.0, 1.0, 0.0),
+        radius: 1.5,
+        material: diffuse(Color::new(0.8, 0.7, 0.3)),
+    }));
+
+    let view_plane = ViewPlane {
+        hres: 600,
+        vres: 500,
+        pixel_size: 1.0,
+    };
+
+    let cam = cameras::PinholeCamera::with_fov(
+        CameraCore::new(
+            Vector3::new(0.0, 2.0, 3.9),
+            Vector3::new(0.0, 1.6, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            ),
+        &view_plane, 60.0);
+
+    Ok(Scene::new(all_objects, black(), Box::new(cam), config, view_plane))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(name: &str, models_dir: &str) -> io::Result<Scene> {
+        let mut config = Config::default();
+        config.scene_name = String::from(name);
+        config.models_dir = String::from(models_dir);
+        lookup_scene(&config.scene_name).unwrap()(&config)
+    }
+
+    #[test]
+    fn obj_models() {
+        // Tests run in the crate's directory, where the default models
+        // directory is.
+        assert!(build("obj1", DEFAULT_MODELS_DIR).is_ok());
+
+        let e = build("obj1", "no-such-models").err().expect("missing model loaded");
+        assert!(e.to_string().contains("no-such-models"));
+    }
+
+    #[test]
+    fn ply_models() {
+        assert!(build("ply1", DEFAULT_MODELS_DIR).is_ok());
+
+        let e = build("ply1", "no-such-models").err().expect("missing model loaded");
+        assert!(e.to_string().contains("no-such-models"));
     }
 }
@@ -0,0 +1,205 @@
+
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use types::*;
+use tiles::Tile;
+use accumulator::Accumulator;
+use render;
+use stats::RenderStats;
+
+pub enum Command {
+    // Replace the current scene. The image buffer is resized to match
+    // the scene's view plane, and the scene is prepared (see
+    // Scene::prepare).
+    SetScene(Scene),
+    // Render the current scene. Ignored if no scene has been set.
+    Render,
+    // Stop the render in progress, if any, and discard any render
+    // request that has not started yet.
+    Cancel,
+    // Stop the service thread.
+    Shutdown,
+}
+
+pub enum Event {
+    // Sample sets are being generated.
+    SettingUp,
+    RenderStarted,
+    // A tile has been written to the image buffer.
+    ChunkFinished(Tile),
+    // A progressive pass (numbered from zero) has finished; the image
+    // buffer holds the running average of all passes so far.
+    PassFinished(usize),
+    RenderDone(RenderStats),
+    // The render was stopped by a Cancel command. The image buffer
+    // holds the tiles that were completed before it stopped.
+    RenderCancelled,
+}
+
+// A long-lived renderer running on its own thread. Commands are sent to
+// it with send() and events are read from events(). The image buffer
+// is updated as chunks finish, so it can be read while a render is in
+// progress.
+pub struct RenderService {
+    commands: Sender<Command>,
+    events: Receiver<Event>,
+    image: Arc<Mutex<Image>>,
+    cancel: CancelToken,
+    // The number of Cancel commands sent, so the service thread can
+    // tell whether the token was tripped by one it hasn't read yet.
+    cancels_sent: Arc<AtomicUsize>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct ServiceObserver {
+    events: Mutex<Sender<Event>>,
+    image: Arc<Mutex<Image>>,
+}
+
+impl ServiceObserver {
+    fn emit(&self, e: Event) {
+        self.events.lock().unwrap().send(e).unwrap();
+    }
+}
+
+impl RenderObserver for ServiceObserver {
+    fn setting_up(&self) {
+        self.emit(Event::SettingUp);
+    }
+
+    fn render_started(&self) {
+        self.emit(Event::RenderStarted);
+    }
+
+    fn tile_finished(&self, tile: &Tile, acc: &Accumulator) {
+        self.image.lock().unwrap().set_tile(tile, &acc.tile_pixels(tile));
+        self.emit(Event::ChunkFinished(*tile));
+    }
+
+    fn pass_finished(&self, pass: usize, _acc: &Accumulator) {
+        self.emit(Event::PassFinished(pass));
+    }
+}
+
+impl RenderService {
+    pub fn start() -> RenderService {
+        let (command_tx, command_rx) = channel();
+        let (event_tx, event_rx) = channel();
+        let image = Arc::new(Mutex::new(Image::new(0, 0)));
+        let cancel = CancelToken::new();
+        let cancels_sent = Arc::new(AtomicUsize::new(0));
+
+        let observer = ServiceObserver {
+            events: Mutex::new(event_tx),
+            image: image.clone(),
+        };
+
+        let thread_cancel = cancel.clone();
+        let thread_cancels_sent = cancels_sent.clone();
+        let thread = thread::spawn(move ||
+            run(command_rx, observer, thread_cancel, thread_cancels_sent));
+
+        RenderService {
+            commands: command_tx,
+            events: event_rx,
+            image,
+            cancel,
+            cancels_sent,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn send(&self, c: Command) {
+        // The service thread can't read commands while it is rendering,
+        // so a cancellation also trips the token the render is polling.
+        if let Command::Cancel = c {
+            self.cancels_sent.fetch_add(1, Ordering::SeqCst);
+            self.cancel.cancel();
+        }
+        self.commands.send(c).unwrap();
+    }
+
+    pub fn events(&self) -> &Receiver<Event> {
+        &self.events
+    }
+
+    pub fn image(&self) -> Arc<Mutex<Image>> {
+        self.image.clone()
+    }
+}
+
+impl Drop for RenderService {
+    // Stop any render in progress, as a Cancel would, rather than wait
+    // for it to finish.
+    fn drop(&mut self) {
+        self.cancels_sent.fetch_add(1, Ordering::SeqCst);
+        self.cancel.cancel();
+        let _ = self.commands.send(Command::Cancel);
+        let _ = self.commands.send(Command::Shutdown);
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+fn run(commands: Receiver<Command>, observer: ServiceObserver, cancel: CancelToken,
+       cancels_sent: Arc<AtomicUsize>) {
+    let mut scene: Option<Scene> = None;
+    let mut bvh_build = Duration::default();
+    let mut cancels_read = 0;
+
+    // Block for the next command, then drain everything else that is
+    // already queued so a Cancel sent right after a Render takes
+    // effect before the render starts.
+    while let Ok(first) = commands.recv() {
+        let mut render_requested = false;
+        let mut batch = vec![first];
+        batch.extend(commands.try_iter());
+
+        for c in batch {
+            match c {
+                Command::SetScene(mut s) => {
+                    *observer.image.lock().unwrap() =
+                        Image::new(s.view_plane.hres, s.view_plane.vres);
+                    bvh_build = s.prepare();
+                    scene = Some(s);
+                },
+                Command::Render => render_requested = true,
+                Command::Cancel => {
+                    render_requested = false;
+                    cancels_read += 1;
+                },
+                Command::Shutdown => return,
+            }
+        }
+
+        // Cancels that have been read have taken effect, so clear the
+        // token, but trip it again if a Cancel was sent that is not in
+        // this batch: it came after the Render and must stop it. send()
+        // counts a Cancel before tripping the token, so one sent while
+        // this runs is either seen here or trips the token after the
+        // reset.
+        cancel.reset();
+        if cancels_sent.load(Ordering::SeqCst) != cancels_read {
+            cancel.cancel();
+        }
+
+        if let (true, Some(s)) = (render_requested, scene.as_ref()) {
+            *observer.image.lock().unwrap() =
+                Image::new(s.view_plane.hres, s.view_plane.vres);
+            let (img, mut stats) = render::render(s, s.camera.as_ref(), &observer, &cancel);
+            stats.bvh_build = bvh_build;
+            *observer.image.lock().unwrap() = img;
+            if cancel.is_cancelled() {
+                observer.emit(Event::RenderCancelled);
+            } else {
+                observer.emit(Event::RenderDone(stats));
+            }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+
+extern crate nalgebra;
+
+use nalgebra::{Vector2, Vector3};
+
+use types::*;
+use bvh::Aabb;
+use constants::*;
+
+// A solid axis-aligned box between the corners min and max. Its normals
+// point outwards, and each face has texture coordinates running from 0
+// to 1 across it. The box may be flat along any axis; build it with
+// Cuboid::new, which checks that min is below max.
+pub struct Cuboid {
+    pub min: Vector3<f64>,
+    pub max: Vector3<f64>,
+    pub material: Box<Material>,
+}
+
+impl Cuboid {
+    pub fn new(min: Vector3<f64>, max: Vector3<f64>, material: Box<Material>) -> Cuboid {
+        if (0..3).any(|axis| min[axis] > max[axis]) {
+            panic!("Cuboid minimum corner {:?} is not below its maximum corner {:?}",
+                   min.as_slice(), max.as_slice());
+        }
+        Cuboid { min, max, material }
+    }
+}
+
+impl Intersectable for Cuboid {
+    fn hit<'a>(&'a self, r: &Ray) -> Option<Hit<'a>> {
+        // Intersect the slabs between each pair of faces, remembering
+        // which axis the ray enters and leaves the box on.
+        let (mut t_near, mut t_far) = (::std::f64::NEG_INFINITY, ::std::f64::INFINITY);
+        let (mut near_axis, mut far_axis) = (0, 0);
+        for axis in 0..3 {
+            // Parallel to the faces on this axis, where a ray along one of
+            // them would give 0 * inf = NaN below.
+            if r.direction[axis] == 0.0 {
+                if r.origin[axis] < self.min[axis] || r.origin[axis] > self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let inv = 1.0 / r.direction[axis];
+            let t0 = (self.min[axis] - r.origin[axis]) * inv;
+            let t1 = (self.max[axis] - r.origin[axis]) * inv;
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            if t0 > t_near {
+                t_near = t0;
+                near_axis = axis;
+            }
+            if t1 < t_far {
+                t_far = t1;
+                far_axis = axis;
+            }
+        }
+
+        if t_near > t_far {
+            return None;
+        }
+
+        // Rays starting inside the box hit it on the way out.
+        let (t, axis, sign) = if t_near > T_MIN {
+            (t_near, near_axis, -r.direction[near_axis].signum())
+        } else if t_far > T_MIN {
+            (t_far, far_axis, r.direction[far_axis].signum())
+        } else {
+            return None;
+        };
+
+        let p = r.point_at_distance(t);
+        let mut normal = Vector3::zeros();
+        normal[axis] = sign;
+
+        // Faces of a box that is flat along the other axes get 0 for
+        // those coordinates.
+        let size = self.max - self.min;
+        let coordinate = |a: usize| {
+            if size[a] > 0.0 { (p[a] - self.min[a]) / size[a] } else { 0.0 }
+        };
+        let uv = Vector2::new(coordinate((axis + 1) % 3), coordinate((axis + 2) % 3));
+
+        Some(Hit {
+            point: p,
+            distance: t,
+            normal,
+            uv,
+            color: Color::all(1.0),
+            material: self.material.as_ref(),
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use materials::lambertian::Lambertian;
+
+    fn cuboid(min: Vector3<f64>, max: Vector3<f64>) -> Cuboid {
+        Cuboid::new(min, max, Box::new(Lambertian { albedo: Color::all(1.0) }))
+    }
+
+    fn unit_cube() -> Cuboid {
+        cuboid(Vector3::zeros(), Vector3::repeat(1.0))
+    }
+
+    fn ray(origin: Vector3<f64>, direction: Vector3<f64>) -> Ray {
+        Ray { origin, direction: direction.normalize(), time: 0.0 }
+    }
+
+    // Straight down onto the top of the unit cube at (x, y).
+    fn down(x: f64, y: f64) -> Ray {
+        ray(Vector3::new(x, y, 3.0), Vector3::new(0.0, 0.0, -1.0))
+    }
+
+    fn contains(b: &Aabb, p: &Vector3<f64>) -> bool {
+        (0..3).all(|a| b.min[a] - 1e-9 <= p[a] && p[a] <= b.max[a] + 1e-9)
+    }
+
+    fn assert_close(a: Vector3<f64>, b: Vector3<f64>) {
+        assert!((a - b).norm() < 1e-9, "{:?} != {:?}", a.as_slice(), b.as_slice());
+    }
+
+    #[test]
+    fn hit_and_miss() {
+        let c = unit_cube();
+
+        let h = c.hit(&down(0.25, 0.5)).unwrap();
+        assert!((h.distance - 2.0).abs() < 1e-9);
+        assert_close(h.point, Vector3::new(0.25, 0.5, 1.0));
+        assert_close(h.normal, Vector3::new(0.0, 0.0, 1.0));
+        assert!((h.uv.x - 0.25).abs() < 1e-9 && (h.uv.y - 0.5).abs() < 1e-9);
+
+        assert!(c.hit(&down(1.5, 0.5)).is_none());
+        assert!(c.hit(&down(0.5, -0.1)).is_none());
+        assert!(c.hit(&ray(Vector3::new(0.5, 0.5, 3.0), Vector3::new(0.0, 0.0, 1.0))).is_none());
+        assert!(c.hit(&ray(Vector3::new(3.0, 3.0, 3.0), Vector3::new(1.0, -1.0, -1.0))).is_none());
+    }
+
+    #[test]
+    fn faces() {
+        // From outside each face towards the center, the normal faces
+        // back along the ray.
+        let c = unit_cube();
+        let center = Vector3::repeat(0.5);
+        for axis in 0..3 {
+            for &sign in [-1.0, 1.0].iter() {
+                let mut outwards = Vector3::zeros();
+                outwards[axis] = sign;
+                let h = c.hit(&ray(center + outwards * 2.0, -outwards)).unwrap();
+                assert!((h.distance - 1.5).abs() < 1e-9);
+                assert_close(h.normal, outwards);
+                assert!(h.uv.iter().all(|&c| (c - 0.5).abs() < 1e-9));
+            }
+        }
+    }
+
+    #[test]
+    fn edges_and_corners() {
+        let c = unit_cube();
+        for &(x, y) in [(1.0, 0.5), (0.0, 0.5), (0.5, 1.0), (0.0, 0.0), (1.0, 1.0)].iter() {
+            let h = c.hit(&down(x, y)).expect("edge or corner missed");
+            assert_close(h.point, Vector3::new(x, y, 1.0));
+            assert_close(h.normal, Vector3::new(0.0, 0.0, 1.0));
+        }
+
+        let h = c.hit(&ray(Vector3::repeat(2.0), Vector3::repeat(-1.0))).unwrap();
+        assert_close(h.point, Vector3::repeat(1.0));
+
+        // Along a face, parallel to it.
+        let h = c.hit(&ray(Vector3::new(-1.0, 0.5, 1.0), Vector3::new(1.0, 0.0, 0.0))).unwrap();
+        assert_close(h.point, Vector3::new(0.0, 0.5, 1.0));
+    }
+
+    #[test]
+    fn from_inside() {
+        // The far face is hit, with its normal still pointing out.
+        let c = unit_cube();
+        let h = c.hit(&ray(Vector3::repeat(0.5), Vector3::new(0.0, 0.0, 1.0))).unwrap();
+        assert!((h.distance - 0.5).abs() < 1e-9);
+        assert_close(h.normal, Vector3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn flat() {
+        let c = cuboid(Vector3::zeros(), Vector3::new(1.0, 1.0, 0.0));
+
+        let h = c.hit(&ray(Vector3::new(0.5, 0.5, 1.0), Vector3::new(0.0, 0.0, -1.0))).unwrap();
+        assert_close(h.normal, Vector3::new(0.0, 0.0, 1.0));
+        let h = c.hit(&ray(Vector3::new(0.5, 0.5, -1.0), Vector3::new(0.0, 0.0, 1.0))).unwrap();
+        assert_close(h.normal, Vector3::new(0.0, 0.0, -1.0));
+
+        // Edge on, the flat axis gets a texture coordinate of 0.
+        let h = c.hit(&ray(Vector3::new(-1.0, 0.5, 0.0), Vector3::new(1.0, 0.0, 0.0))).unwrap();
+        assert_close(h.normal, Vector3::new(-1.0, 0.0, 0.0));
+        assert!(h.uv.iter().all(|c| c.is_finite()));
+        assert!((h.uv.x - 0.5).abs() < 1e-9 && h.uv.y == 0.0);
+    }
+
+    #[test]
+    fn bounds() {
+        let c = cuboid(Vector3::new(-1.0, 2.0, 0.5), Vector3::new(3.0, 2.5, 4.0));
+        let b = c.bounds().unwrap();
+        for i in 0..100 {
+            let x = i as f64 * 0.37;
+            let origin = Vector3::new(x.sin() * 10.0, x.cos() * 10.0, (x * 3.0).sin() * 10.0);
+            let target = Vector3::new(1.0 + x.cos(), 2.25 + (x * 2.0).sin() * 0.2, 2.0 + x.sin());
+            let h = c.hit(&ray(origin, target - origin)).expect("ray towards the inside missed");
+            assert!(contains(&b, &h.point));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn inverted_corners() {
+        cuboid(Vector3::repeat(1.0), Vector3::zeros());
+    }
+}
@@ -1,3 +1,40 @@
 
 pub mod sphere;
 pub mod plane;
+pub mod triangle;
+pub mod cuboid;
+
+extern crate nalgebra;
+
+use nalgebra::Vector3;
+
+use types::*;
+use constants::*;
+
+// The distance at which the ray meets the plane through point with the
+// given normal, if it does so in front of the ray origin.
+fn hit_plane(point: &Vector3<f64>, normal: &Vector3<f64>, r: &Ray) -> Option<f64> {
+    let denom = r.direction.dot(normal);
+    // The ray is parallel to the plane.
+    if denom.abs() < 1e-12 {
+        return None;
+    }
+
+    let t = (point - r.origin).dot(normal) / denom;
+    if t > T_MIN {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+// Flat shapes other than Plane are two-sided: flip the shading normal
+// to the side of the surface the ray came from, as materials expect.
+// The geometric normal decides which side that is.
+fn facing(shading: &Vector3<f64>, geometric: &Vector3<f64>, r: &Ray) -> Vector3<f64> {
+    if r.direction.dot(geometric) > 0.0 {
+        -shading
+    } else {
+        *shading
+    }
+}
@@ -1,10 +1,11 @@
 
 extern crate nalgebra;
 
-use nalgebra::{Vector3, Point3};
+use nalgebra::{Vector2, Vector3, Point3};
 
 use types::*;
-use constants::*;
+use bvh::Aabb;
+use super::{facing, hit_plane};
 
 pub struct Plane {
     pub origin: Point3<f64>,
@@ -14,17 +15,255 @@ pub struct Plane {
 
 impl Intersectable for Plane {
     fn hit<'a>(&'a self, r: &Ray) -> Option<Hit<'a>> {
-        let t = (self.origin.coords - r.origin).dot(&self.normal) / (r.direction.dot(&self.normal));
-
-        if t > T_MIN {
-            Some(Hit {
-                point: r.origin + t * r.direction,
-                distance: t,
-                normal: self.normal,
-                material: self.material.as_ref(),
-            })
-        } else {
-            None
+        let t = hit_plane(&self.origin.coords, &self.normal, r)?;
+
+        Some(Hit {
+            point: r.origin + t * r.direction,
+            distance: t,
+            normal: self.normal,
+            // Planes have no natural texture coordinates.
+            uv: Vector2::zeros(),
+            color: Color::all(1.0),
+            material: self.material.as_ref(),
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+// A flat parallelogram with one corner at corner and sides edge1 and
+// edge2, e.g. a wall, a table top or a rectangular area light. It is
+// two-sided, and its texture coordinates run from 0 to 1 along each
+// edge.
+pub struct Rectangle {
+    pub corner: Vector3<f64>,
+    pub edge1: Vector3<f64>,
+    pub edge2: Vector3<f64>,
+    pub material: Box<Material>,
+}
+
+impl Intersectable for Rectangle {
+    fn hit<'a>(&'a self, r: &Ray) -> Option<Hit<'a>> {
+        let n = self.edge1.cross(&self.edge2);
+        let t = hit_plane(&self.corner, &n, r)?;
+        let p = r.point_at_distance(t);
+
+        // The hit point's coordinates along the edges.
+        let w = n / n.dot(&n);
+        let rel = p - self.corner;
+        let u = w.dot(&rel.cross(&self.edge2));
+        let v = w.dot(&self.edge1.cross(&rel));
+        if u < 0.0 || u > 1.0 || v < 0.0 || v > 1.0 {
+            return None;
+        }
+
+        Some(Hit {
+            point: p,
+            distance: t,
+            normal: facing(&n.normalize(), &n, r),
+            uv: Vector2::new(u, v),
+            color: Color::all(1.0),
+            material: self.material.as_ref(),
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::around(&[
+            self.corner,
+            self.corner + self.edge1,
+            self.corner + self.edge2,
+            self.corner + self.edge1 + self.edge2,
+        ]))
+    }
+}
+
+// A flat, two-sided circle. Its texture coordinates project it onto a
+// unit square, with the center at (0.5, 0.5).
+pub struct Disk {
+    pub center: Vector3<f64>,
+    pub normal: Vector3<f64>,
+    pub radius: f64,
+    pub material: Box<Material>,
+}
+
+impl Intersectable for Disk {
+    fn hit<'a>(&'a self, r: &Ray) -> Option<Hit<'a>> {
+        let n = self.normal.normalize();
+        let t = hit_plane(&self.center, &n, r)?;
+        let p = r.point_at_distance(t);
+
+        let rel = p - self.center;
+        if rel.norm_squared() > self.radius * self.radius {
+            return None;
+        }
+
+        // Any two directions in the disk will do for its texture axes.
+        let other = if n.x.abs() < 0.9 { Vector3::x() } else { Vector3::y() };
+        let u_axis = n.cross(&other).normalize();
+        let v_axis = n.cross(&u_axis);
+        let scale = 0.5 / self.radius;
+
+        Some(Hit {
+            point: p,
+            distance: t,
+            normal: facing(&n, &n, r),
+            uv: Vector2::new(0.5 + rel.dot(&u_axis) * scale, 0.5 + rel.dot(&v_axis) * scale),
+            color: Color::all(1.0),
+            material: self.material.as_ref(),
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        // Along each axis the disk reaches out by its radius times the
+        // sine of the angle between the axis and its normal.
+        let n = self.normal.normalize();
+        let extent = n.map(|c| self.radius * (1.0 - c * c).max(0.0).sqrt());
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use materials::lambertian::Lambertian;
+
+    fn material() -> Box<Material> {
+        Box::new(Lambertian { albedo: Color::all(1.0) })
+    }
+
+    fn ray(origin: Vector3<f64>, direction: Vector3<f64>) -> Ray {
+        Ray { origin, direction: direction.normalize(), time: 0.0 }
+    }
+
+    // Straight down onto the z = 0 plane at (x, y), or up from below it.
+    fn down(x: f64, y: f64) -> Ray {
+        ray(Vector3::new(x, y, 1.0), Vector3::new(0.0, 0.0, -1.0))
+    }
+
+    fn up(x: f64, y: f64) -> Ray {
+        ray(Vector3::new(x, y, -1.0), Vector3::new(0.0, 0.0, 1.0))
+    }
+
+    fn contains(b: &Aabb, p: &Vector3<f64>) -> bool {
+        (0..3).all(|a| b.min[a] - 1e-9 <= p[a] && p[a] <= b.max[a] + 1e-9)
+    }
+
+    fn assert_close(a: Vector3<f64>, b: Vector3<f64>) {
+        assert!((a - b).norm() < 1e-9, "{:?} != {:?}", a.as_slice(), b.as_slice());
+    }
+
+    // A parallelogram leaning over in x.
+    fn rectangle() -> Rectangle {
+        Rectangle {
+            corner: Vector3::zeros(),
+            edge1: Vector3::new(2.0, 0.0, 0.0),
+            edge2: Vector3::new(1.0, 1.0, 0.0),
+            material: material(),
+        }
+    }
+
+    #[test]
+    fn rectangle_hit_and_miss() {
+        let r = rectangle();
+
+        let h = r.hit(&down(1.5, 0.5)).unwrap();
+        assert!((h.distance - 1.0).abs() < 1e-9);
+        assert_close(h.normal, Vector3::new(0.0, 0.0, 1.0));
+        assert!((h.uv.x - 0.5).abs() < 1e-9 && (h.uv.y - 0.5).abs() < 1e-9);
+
+        assert!(r.hit(&down(0.2, 0.5)).is_none());
+        assert!(r.hit(&down(2.8, 0.5)).is_none());
+        assert!(r.hit(&down(1.5, 1.1)).is_none());
+        assert!(r.hit(&down(1.5, -0.1)).is_none());
+        assert!(r.hit(&ray(Vector3::new(-1.0, 0.5, 0.0), Vector3::new(1.0, 0.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn rectangle_edges_and_corners() {
+        let r = rectangle();
+        for &(x, y, u, v) in [(0.0, 0.0, 0.0, 0.0), (2.0, 0.0, 1.0, 0.0), (1.0, 1.0, 0.0, 1.0),
+                              (3.0, 1.0, 1.0, 1.0), (2.5, 0.5, 1.0, 0.5), (1.5, 1.0, 0.25, 1.0)].iter() {
+            let h = r.hit(&down(x, y)).expect("edge or corner missed");
+            assert!((h.uv.x - u).abs() < 1e-9 && (h.uv.y - v).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn rectangle_two_sided() {
+        let h = rectangle().hit(&up(1.5, 0.5)).map(|h| h.normal).unwrap();
+        assert_close(h, Vector3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn rectangle_bounds() {
+        let r = Rectangle {
+            corner: Vector3::new(1.0, -2.0, 0.5),
+            edge1: Vector3::new(1.0, 2.0, 3.0),
+            edge2: Vector3::new(-2.0, 1.0, 0.0),
+            material: material(),
+        };
+        let b = r.bounds().unwrap();
+        for i in 0..11 {
+            for j in 0..11 {
+                let p = r.corner + r.edge1 * (i as f64 / 10.0) + r.edge2 * (j as f64 / 10.0);
+                assert!(contains(&b, &p));
+            }
+        }
+    }
+
+    fn disk(normal: Vector3<f64>) -> Disk {
+        Disk { center: Vector3::zeros(), normal, radius: 1.0, material: material() }
+    }
+
+    #[test]
+    fn disk_hit_and_miss() {
+        // The normal needn't be unit length.
+        let d = disk(Vector3::new(0.0, 0.0, 2.0));
+
+        let h = d.hit(&down(0.0, 0.0)).unwrap();
+        assert!((h.distance - 1.0).abs() < 1e-9);
+        assert_close(h.normal, Vector3::new(0.0, 0.0, 1.0));
+        assert!((h.uv.x - 0.5).abs() < 1e-9 && (h.uv.y - 0.5).abs() < 1e-9);
+
+        assert!(d.hit(&down(0.75, 0.75)).is_none());
+        assert!(d.hit(&down(0.0, 1.1)).is_none());
+        assert!(d.hit(&ray(Vector3::new(-2.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn disk_edge() {
+        let d = disk(Vector3::new(0.0, 0.0, 1.0));
+        for &(x, y) in [(1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0)].iter() {
+            let h = d.hit(&down(x, y)).expect("rim missed");
+            // The rim touches the sides of the texture.
+            assert!(h.uv.iter().all(|&c| c > -1e-9 && c < 1.0 + 1e-9));
+            assert!(h.uv.iter().any(|&c| c.abs() < 1e-9 || (c - 1.0).abs() < 1e-9));
+        }
+    }
+
+    #[test]
+    fn disk_two_sided() {
+        let h = disk(Vector3::new(0.0, 0.0, 1.0)).hit(&up(0.5, 0.0)).map(|h| h.normal).unwrap();
+        assert_close(h, Vector3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn disk_bounds() {
+        for n in [Vector3::new(0.0, 0.0, 1.0), Vector3::new(1.0, 1.0, 0.0),
+                  Vector3::new(1.0, -2.0, 3.0)].iter() {
+            let d = Disk { center: Vector3::new(1.0, 2.0, 3.0), normal: *n, radius: 2.0,
+                           material: material() };
+            let b = d.bounds().unwrap();
+
+            let n = n.normalize();
+            let u = n.cross(&Vector3::new(n.y, n.z, -n.x)).normalize();
+            let v = n.cross(&u);
+            for i in 0..64 {
+                let a = i as f64 * ::std::f64::consts::PI / 32.0;
+                assert!(contains(&b, &(d.center + (u * a.cos() + v * a.sin()) * d.radius)));
+            }
         }
     }
 }
@@ -1,9 +1,12 @@
 
 extern crate nalgebra;
 
-use nalgebra::{Vector3};
+use nalgebra::{Vector2, Vector3};
+
+use std::f64::consts::PI;
 
 use types::*;
+use bvh::Aabb;
 use constants::*;
 
 pub struct Sphere {
@@ -14,43 +17,104 @@ pub struct Sphere {
 
 impl Intersectable for Sphere {
     fn hit<'a>(&'a self, r: &Ray) -> Option<Hit<'a>> {
-        let oc = r.origin - self.center;
-        let a = r.direction.dot(&r.direction);
-        let b = 2.0 * oc.dot(&r.direction);
-        let c = oc.dot(&oc) - self.radius * self.radius;
-        let discriminant = b * b - 4.0 * a * c;
+        hit_sphere(&self.center, self.radius, self.material.as_ref(), r)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(sphere_bounds(&self.center, self.radius))
+    }
+}
+
+// A sphere that moves in a straight line from start_center at
+// start_time to end_center at end_time, for motion blur. It rests at
+// start_center before start_time and at end_center after end_time, so
+// its bounds hold at any time.
+pub struct MovingSphere {
+    pub start_center: Vector3<f64>,
+    pub end_center: Vector3<f64>,
+    pub start_time: f64,
+    pub end_time: f64,
+    pub radius: f64,
+    pub material: Box<Material>,
+}
+
+impl MovingSphere {
+    pub fn center(&self, time: f64) -> Vector3<f64> {
+        if self.end_time == self.start_time {
+            return self.start_center;
+        }
+        let t = (time - self.start_time) / (self.end_time - self.start_time);
+        self.start_center + t.max(0.0).min(1.0) * (self.end_center - self.start_center)
+    }
+}
+
+impl Intersectable for MovingSphere {
+    fn hit<'a>(&'a self, r: &Ray) -> Option<Hit<'a>> {
+        hit_sphere(&self.center(r.time), self.radius, self.material.as_ref(), r)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(sphere_bounds(&self.start_center, self.radius)
+             .union(&sphere_bounds(&self.end_center, self.radius)))
+    }
+}
 
-        if discriminant > 0.0 {
-            let t1 = (-b - discriminant.sqrt()) / (2.0 * a);
+fn sphere_bounds(center: &Vector3<f64>, radius: f64) -> Aabb {
+    let r = Vector3::repeat(radius);
+    Aabb::new(center - r, center + r)
+}
+
+fn hit_sphere<'a>(center: &Vector3<f64>, radius: f64, material: &'a Material,
+                  r: &Ray) -> Option<Hit<'a>> {
+    let oc = r.origin - center;
+    let a = r.direction.dot(&r.direction);
+    let b = 2.0 * oc.dot(&r.direction);
+    let c = oc.dot(&oc) - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
 
-            if t1 > T_MIN {
-                let p = r.point_at_distance(t1);
+    if discriminant > 0.0 {
+        let t1 = (-b - discriminant.sqrt()) / (2.0 * a);
+
+        if t1 > T_MIN {
+            let p = r.point_at_distance(t1);
+            let normal = (p - center) / radius;
+            Some(Hit {
+                point: p,
+                distance: t1,
+                normal,
+                uv: sphere_uv(&normal),
+                color: Color::all(1.0),
+                material,
+            })
+        } else {
+            let t2 = (-b + (b * b - a * c).sqrt()) / a;
+            if t2 > T_MIN {
+                let p = r.point_at_distance(t2);
+                let normal = (p - center) / radius;
                 Some(Hit {
                     point: p,
-                    distance: t1,
-                    normal: (p - self.center) / self.radius,
-                    material: self.material.as_ref(),
+                    distance: t2,
+                    normal,
+                    uv: sphere_uv(&normal),
+                    color: Color::all(1.0),
+                    material,
                 })
             } else {
-                let t2 = (-b + (b * b - a * c).sqrt()) / a;
-                if t2 > T_MIN {
-                    let p = r.point_at_distance(t2);
-                    Some(Hit {
-                        point: p,
-                        distance: t2,
-                        normal: (p - self.center) / self.radius,
-                        material: self.material.as_ref(),
-                    })
-                } else {
-                    None
-                }
+                None
             }
-        } else {
-            None
         }
+    } else {
+        None
     }
 }
 
+// Longitude and latitude of a point on the unit sphere, scaled to
+// [0..1], with v increasing towards +y.
+fn sphere_uv(n: &Vector3<f64>) -> Vector2<f64> {
+    Vector2::new(0.5 + n.z.atan2(n.x) / (2.0 * PI),
+                 0.5 + n.y.max(-1.0).min(1.0).asin() / PI)
+}
+
 pub struct SampleSphere {
     pub sphere: Sphere,
     pub samples: Vec<Vector3<f64>>,
@@ -71,4 +135,8 @@ impl Intersectable for SampleSphere {
             }
         }
     }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.sphere.bounds()
+    }
 }
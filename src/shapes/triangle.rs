@@ -0,0 +1,420 @@
+
+extern crate nalgebra;
+
+use nalgebra::{Vector2, Vector3};
+
+use types::*;
+use bvh::{Aabb, Bvh};
+use stats::RayStats;
+use constants::*;
+use super::facing;
+
+// A single flat triangle. Its texture coordinates are the barycentric
+// coordinates of the hit point relative to v1 and v2.
+pub struct Triangle {
+    pub v0: Vector3<f64>,
+    pub v1: Vector3<f64>,
+    pub v2: Vector3<f64>,
+    pub material: Box<Material>,
+}
+
+impl Intersectable for Triangle {
+    fn hit<'a>(&'a self, r: &Ray) -> Option<Hit<'a>> {
+        let (t, b1, b2) = hit_triangle(&self.v0, &self.v1, &self.v2, r)?;
+        let normal = (self.v1 - self.v0).cross(&(self.v2 - self.v0)).normalize();
+
+        Some(Hit {
+            point: r.point_at_distance(t),
+            distance: t,
+            normal: facing(&normal, &normal, r),
+            uv: Vector2::new(b1, b2),
+            color: Color::all(1.0),
+            material: self.material.as_ref(),
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::around(&[self.v0, self.v1, self.v2]))
+    }
+}
+
+// A mesh of triangles sharing vertices. Normals, texture coordinates
+// and colors are given per vertex and interpolated across each
+// triangle, so that normals give smooth shading.
+pub struct TriangleMesh {
+    pub positions: Vec<Vector3<f64>>,
+    // Either of these may be empty. Without normals the triangles are
+    // flat shaded, and without texture coordinates they get barycentric
+    // ones as for Triangle.
+    pub normals: Vec<Vector3<f64>>,
+    pub uvs: Vec<Vector2<f64>>,
+    // Empty unless set with with_colors.
+    pub colors: Vec<Color>,
+    // Three vertex indices per triangle.
+    pub indices: Vec<[usize; 3]>,
+    pub material: Box<Material>,
+    // Built by build_bvh; changing the mesh's positions or indices
+    // discards it.
+    bvh: Option<Bvh>,
+}
+
+impl TriangleMesh {
+    pub fn new(positions: Vec<Vector3<f64>>, normals: Vec<Vector3<f64>>,
+               uvs: Vec<Vector2<f64>>, indices: Vec<[usize; 3]>,
+               material: Box<Material>) -> TriangleMesh {
+        if !normals.is_empty() && normals.len() != positions.len() {
+            panic!("Mesh has {} vertices but {} normals", positions.len(), normals.len());
+        }
+        if !uvs.is_empty() && uvs.len() != positions.len() {
+            panic!("Mesh has {} vertices but {} texture coordinates",
+                   positions.len(), uvs.len());
+        }
+        if let Some(&i) = indices.iter().flat_map(|t| t.iter()).find(|&&i| i >= positions.len()) {
+            panic!("Mesh vertex index {} out of range ({} vertices)", i, positions.len());
+        }
+
+        TriangleMesh { positions, normals, uvs, colors: vec![], indices, material, bvh: None }
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Color>) -> TriangleMesh {
+        if colors.len() != self.positions.len() {
+            panic!("Mesh has {} vertices but {} colors", self.positions.len(), colors.len());
+        }
+        self.colors = colors;
+        self
+    }
+
+    // Replace the normals with smooth ones, averaged from the faces
+    // around each vertex weighted by their area.
+    pub fn compute_normals(&mut self) {
+        let mut normals = vec![Vector3::zeros(); self.positions.len()];
+        for t in self.indices.iter() {
+            let (p0, p1, p2) = (self.positions[t[0]], self.positions[t[1]], self.positions[t[2]]);
+            // The cross product's length is twice the triangle's area.
+            let n = (p1 - p0).cross(&(p2 - p0));
+            for &i in t.iter() {
+                normals[i] += n;
+            }
+        }
+
+        self.normals = normals.iter()
+            .map(|n| if n.norm() > 0.0 { n.normalize() } else { *n })
+            .collect();
+    }
+
+    // Scale the mesh about the origin, then move it by offset, e.g. to
+    // place a loaded model in a scene.
+    pub fn place(&mut self, scale: f64, offset: Vector3<f64>) {
+        for p in self.positions.iter_mut() {
+            *p = *p * scale + offset;
+        }
+        self.bvh = None;
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn hit_index(&self, i: usize, r: &Ray) -> Option<(f64, f64, f64)> {
+        let t = &self.indices[i];
+        hit_triangle(&self.positions[t[0]], &self.positions[t[1]], &self.positions[t[2]], r)
+    }
+
+    fn triangle_bounds(&self, i: usize) -> Aabb {
+        let t = &self.indices[i];
+        Aabb::around(&[self.positions[t[0]], self.positions[t[1]], self.positions[t[2]]])
+    }
+}
+
+impl Intersectable for TriangleMesh {
+    fn hit<'a>(&'a self, r: &Ray) -> Option<Hit<'a>> {
+        self.hit_with_stats(r, &mut RayStats::default())
+    }
+
+    fn hit_with_stats<'a>(&'a self, r: &Ray, stats: &mut RayStats) -> Option<Hit<'a>> {
+        let closest = match self.bvh {
+            Some(ref bvh) => bvh.hit(r, |i, t_max| {
+                stats.intersection_tests += 1;
+                self.hit_index(i, r).filter(|h| h.0 < t_max).map(|h| (h.0, (i, h)))
+            }),
+            None => {
+                stats.intersection_tests += self.indices.len();
+                let mut closest: Option<(usize, (f64, f64, f64))> = None;
+                for i in 0..self.indices.len() {
+                    if let Some(h) = self.hit_index(i, r) {
+                        if closest.map_or(true, |(_, c)| h.0 < c.0) {
+                            closest = Some((i, h));
+                        }
+                    }
+                }
+                closest
+            },
+        };
+
+        let (i, (t, b1, b2)) = closest?;
+        let [i0, i1, i2] = self.indices[i];
+        let b0 = 1.0 - b1 - b2;
+
+        let (p0, p1, p2) = (self.positions[i0], self.positions[i1], self.positions[i2]);
+        let geometric = (p1 - p0).cross(&(p2 - p0)).normalize();
+        let shading = if self.normals.is_empty() {
+            geometric
+        } else {
+            let n = b0 * self.normals[i0] + b1 * self.normals[i1] + b2 * self.normals[i2];
+            if n.norm() > 0.0 { n.normalize() } else { geometric }
+        };
+
+        let uv = if self.uvs.is_empty() {
+            Vector2::new(b1, b2)
+        } else {
+            b0 * self.uvs[i0] + b1 * self.uvs[i1] + b2 * self.uvs[i2]
+        };
+
+        let color = if self.colors.is_empty() {
+            Color::all(1.0)
+        } else {
+            self.colors[i0] * b0 + self.colors[i1] * b1 + self.colors[i2] * b2
+        };
+
+        Some(Hit {
+            point: r.point_at_distance(t),
+            distance: t,
+            normal: facing(&shading, &geometric, r),
+            uv,
+            color,
+            material: self.material.as_ref(),
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::around(&self.positions))
+    }
+
+    fn build_bvh(&mut self) {
+        let bounds: Vec<Option<Aabb>> = (0..self.indices.len())
+            .map(|i| Some(self.triangle_bounds(i)))
+            .collect();
+        self.bvh = Some(Bvh::build(&bounds));
+    }
+}
+
+// Moller-Trumbore intersection, giving the distance along the ray and
+// the barycentric coordinates of the hit point relative to v1 and v2.
+fn hit_triangle(v0: &Vector3<f64>, v1: &Vector3<f64>, v2: &Vector3<f64>,
+                r: &Ray) -> Option<(f64, f64, f64)> {
+    let e1 = v1 - v0;
+    let e2 = v2 - v0;
+    let p = r.direction.cross(&e2);
+    let det = e1.dot(&p);
+
+    // The ray is parallel to the triangle.
+    if det.abs() < 1e-12 {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = r.origin - v0;
+    let b1 = s.dot(&p) * inv_det;
+    if b1 < 0.0 || b1 > 1.0 {
+        return None;
+    }
+
+    let q = s.cross(&e1);
+    let b2 = r.direction.dot(&q) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = e2.dot(&q) * inv_det;
+    if t > T_MIN {
+        Some((t, b1, b2))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use materials::lambertian::Lambertian;
+
+    fn material() -> Box<Material> {
+        Box::new(Lambertian { albedo: Color::all(1.0) })
+    }
+
+    fn ray(origin: Vector3<f64>, direction: Vector3<f64>) -> Ray {
+        Ray { origin, direction: direction.normalize(), time: 0.0 }
+    }
+
+    // A ray straight down onto the z = 0 plane at (x, y), or straight up
+    // from below it.
+    fn down(x: f64, y: f64) -> Ray {
+        ray(Vector3::new(x, y, 1.0), Vector3::new(0.0, 0.0, -1.0))
+    }
+
+    fn up(x: f64, y: f64) -> Ray {
+        ray(Vector3::new(x, y, -1.0), Vector3::new(0.0, 0.0, 1.0))
+    }
+
+    fn triangle() -> Triangle {
+        Triangle {
+            v0: Vector3::new(0.0, 0.0, 0.0),
+            v1: Vector3::new(1.0, 0.0, 0.0),
+            v2: Vector3::new(0.0, 1.0, 0.0),
+            material: material(),
+        }
+    }
+
+    fn contains(b: &Aabb, p: &Vector3<f64>) -> bool {
+        (0..3).all(|a| b.min[a] - 1e-9 <= p[a] && p[a] <= b.max[a] + 1e-9)
+    }
+
+    fn assert_close(a: Vector3<f64>, b: Vector3<f64>) {
+        assert!((a - b).norm() < 1e-9, "{:?} != {:?}", a.as_slice(), b.as_slice());
+    }
+
+    #[test]
+    fn triangle_hit_and_miss() {
+        let t = triangle();
+
+        let h = t.hit(&down(0.25, 0.5)).unwrap();
+        assert!((h.distance - 1.0).abs() < 1e-9);
+        assert_close(h.point, Vector3::new(0.25, 0.5, 0.0));
+        assert_close(h.normal, Vector3::new(0.0, 0.0, 1.0));
+        assert!((h.uv.x - 0.25).abs() < 1e-9 && (h.uv.y - 0.5).abs() < 1e-9);
+
+        assert!(t.hit(&down(0.6, 0.6)).is_none());
+        assert!(t.hit(&down(-0.1, 0.5)).is_none());
+        assert!(t.hit(&down(0.5, -0.1)).is_none());
+        // Pointing away from the triangle, and parallel to it.
+        assert!(t.hit(&ray(Vector3::new(0.25, 0.25, 1.0), Vector3::new(0.0, 0.0, 1.0))).is_none());
+        assert!(t.hit(&ray(Vector3::new(-1.0, 0.25, 0.0), Vector3::new(1.0, 0.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn triangle_edges_and_corners() {
+        let t = triangle();
+        for &(x, y) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0),
+                        (0.5, 0.0), (0.0, 0.5), (0.5, 0.5)].iter() {
+            let h = t.hit(&down(x, y)).expect("edge or corner missed");
+            assert_close(h.point, Vector3::new(x, y, 0.0));
+        }
+    }
+
+    #[test]
+    fn triangle_two_sided() {
+        let t = triangle();
+        let h = t.hit(&up(0.25, 0.25)).unwrap();
+        assert_close(h.normal, Vector3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn triangle_bounds() {
+        let t = triangle();
+        let b = t.bounds().unwrap();
+        for v in [t.v0, t.v1, t.v2].iter() {
+            assert!(contains(&b, v));
+        }
+    }
+
+    // Two triangles making up the unit square, with per-vertex normals
+    // tilted outwards from its center, texture coordinates and colors.
+    fn square() -> TriangleMesh {
+        let positions = vec![
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(1.0, 1.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        ];
+        let normals = positions.iter()
+            .map(|p| (p - Vector3::new(0.5, 0.5, -1.0)).normalize())
+            .collect();
+        let uvs = positions.iter().map(|p| Vector2::new(p.x, p.y)).collect();
+        let colors = vec![Color::new(1.0, 0.0, 0.0), Color::new(0.0, 1.0, 0.0),
+                          Color::new(0.0, 0.0, 1.0), Color::all(1.0)];
+
+        TriangleMesh::new(positions, normals, uvs, vec![[0, 1, 2], [0, 2, 3]], material())
+            .with_colors(colors)
+    }
+
+    #[test]
+    fn mesh_interpolation() {
+        let mesh = square();
+
+        // At the middle of the edge between vertices 1 and 2.
+        let h = mesh.hit(&down(1.0, 0.5)).unwrap();
+        let expected = (mesh.normals[1] + mesh.normals[2]).normalize();
+        assert_close(h.normal, expected);
+        assert!((h.uv.x - 1.0).abs() < 1e-9 && (h.uv.y - 0.5).abs() < 1e-9);
+        assert!((h.color.r - 0.0).abs() < 1e-9 && (h.color.g - 0.5).abs() < 1e-9 &&
+                (h.color.b - 0.5).abs() < 1e-9);
+
+        // The center, where the normals average to straight up.
+        let h = mesh.hit(&down(0.5, 0.5)).unwrap();
+        assert_close(h.normal, Vector3::new(0.0, 0.0, 1.0));
+
+        // From below the shading normal is flipped with the face.
+        let h = mesh.hit(&up(1.0, 0.5)).unwrap();
+        assert_close(h.normal, -expected);
+    }
+
+    #[test]
+    fn mesh_zero_normals() {
+        // Opposite normals cancel out halfway between them, where the
+        // face normal is used instead.
+        let mut mesh = square();
+        mesh.normals = vec![Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, -1.0),
+                            Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.0, 0.0, 1.0)];
+        let h = mesh.hit(&down(0.5, 0.5)).unwrap();
+        assert_close(h.normal, Vector3::new(0.0, 0.0, 1.0));
+    }
+
+    // A bumpy grid of n x n squares.
+    fn grid(n: usize) -> TriangleMesh {
+        let mut positions = vec![];
+        for y in 0..(n + 1) {
+            for x in 0..(n + 1) {
+                let z = ((x * 7 + y * 3) % 5) as f64 * 0.1;
+                positions.push(Vector3::new(x as f64, y as f64, z));
+            }
+        }
+        let vertex = |x: usize, y: usize| y * (n + 1) + x;
+        let mut indices = vec![];
+        for y in 0..n {
+            for x in 0..n {
+                indices.push([vertex(x, y), vertex(x + 1, y), vertex(x + 1, y + 1)]);
+                indices.push([vertex(x, y), vertex(x + 1, y + 1), vertex(x, y + 1)]);
+            }
+        }
+        TriangleMesh::new(positions, vec![], vec![], indices, material())
+    }
+
+    #[test]
+    fn mesh_bvh() {
+        let n = 20;
+        let linear = grid(n);
+        let mut tree = grid(n);
+        tree.build_bvh();
+        let bounds = tree.bounds().unwrap();
+
+        let mut tested = RayStats::default();
+        for i in 0..400 {
+            let target = Vector3::new((i % 23) as f64 * 0.9, (i % 19) as f64 * 1.1, 0.2);
+            let origin = Vector3::new((i % 7) as f64 * 3.0 - 9.0, (i % 5) as f64 * 4.0 - 8.0, 6.0);
+            let r = ray(origin, target - origin);
+
+            let expected = linear.hit(&r);
+            let found = tree.hit_with_stats(&r, &mut tested);
+            assert_eq!(found.as_ref().map(|h| h.distance), expected.as_ref().map(|h| h.distance));
+            if let Some(h) = found {
+                assert!(contains(&bounds, &h.point));
+            }
+        }
+
+        // Counting each triangle tested shows the hierarchy at work.
+        let mut all = RayStats::default();
+        linear.hit_with_stats(&down(3.5, 3.5), &mut all);
+        assert_eq!(all.intersection_tests, 2 * n * n);
+        assert!(tested.intersection_tests < 400 * 2 * n * n / 10);
+    }
+}
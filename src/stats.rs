@@ -0,0 +1,81 @@
+
+use std::ops::AddAssign;
+use std::time::Duration;
+
+// Counters gathered while tracing rays. Each worker keeps its own and
+// they are summed when the render finishes.
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Default)]
+pub struct RayStats {
+    pub primary_rays: usize,
+    pub total_rays: usize,
+    // Primitives tested for a hit, counting each triangle of a mesh
+    // that is tested.
+    pub intersection_tests: usize,
+}
+
+#[derive(Clone)]
+#[derive(Default)]
+pub struct RenderStats {
+    // Building the scene's bounding volume hierarchy, which happens
+    // before rendering, so callers fill it in.
+    pub bvh_build: Duration,
+    pub sample_generation: Duration,
+    pub rendering: Duration,
+    pub output: Duration,
+    pub rays: RayStats,
+}
+
+impl AddAssign for RayStats {
+    fn add_assign(&mut self, other: RayStats) {
+        self.primary_rays += other.primary_rays;
+        self.total_rays += other.total_rays;
+        self.intersection_tests += other.intersection_tests;
+    }
+}
+
+impl RayStats {
+    // The average number of rays traced per path, including the
+    // primary ray.
+    pub fn average_path_depth(&self) -> f64 {
+        if self.primary_rays == 0 {
+            0.0
+        } else {
+            self.total_rays as f64 / self.primary_rays as f64
+        }
+    }
+}
+
+impl RenderStats {
+    pub fn show(&self) {
+        println!("Render statistics:");
+        println!("  BVH build:           {:.3} s", self.bvh_build.as_secs_f64());
+        println!("  Sample generation:   {:.3} s", self.sample_generation.as_secs_f64());
+        println!("  Rendering:           {:.3} s", self.rendering.as_secs_f64());
+        println!("  Output:              {:.3} s", self.output.as_secs_f64());
+        println!("  Primary rays:        {}", self.rays.primary_rays);
+        println!("  Total rays:          {}", self.rays.total_rays);
+        println!("  Intersection tests:  {}", self.rays.intersection_tests);
+        println!("  Average path depth:  {:.3}", self.rays.average_path_depth());
+    }
+
+    pub fn to_json(&self) -> String {
+        let fields: Vec<(&str, String)> = vec![
+            ("bvh_build_secs", self.bvh_build.as_secs_f64().to_string()),
+            ("sample_generation_secs", self.sample_generation.as_secs_f64().to_string()),
+            ("rendering_secs", self.rendering.as_secs_f64().to_string()),
+            ("output_secs", self.output.as_secs_f64().to_string()),
+            ("primary_rays", self.rays.primary_rays.to_string()),
+            ("total_rays", self.rays.total_rays.to_string()),
+            ("intersection_tests", self.rays.intersection_tests.to_string()),
+            ("average_path_depth", self.rays.average_path_depth().to_string()),
+        ];
+
+        let body: Vec<String> = fields.iter()
+            .map(|&(k, ref v)| format!("  \"{}\": {}", k, v))
+            .collect();
+
+        format!("{{\n{}\n}}\n", body.join(",\n"))
+    }
+}
@@ -0,0 +1,257 @@
+
+use std::cmp::{max, min};
+
+// A rectangular region of the image. The x1 and y1 bounds are
+// exclusive.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+// The order in which tiles are handed out to render threads.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum TileOrder {
+    // Left to right, top to bottom.
+    Scanline,
+    // Outward from the center of the image.
+    Spiral,
+    // Along a Hilbert curve, which keeps consecutive tiles adjacent.
+    Hilbert,
+}
+
+impl Tile {
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+
+    pub fn pixel_count(&self) -> usize {
+        self.width() * self.height()
+    }
+
+    // Parse "x0,y0,x1,y1".
+    pub fn from_corners(s: &str) -> Option<Tile> {
+        let v: Vec<usize> = s.split(',').map(|p| p.trim().parse().ok()).collect::<Option<_>>()?;
+        if v.len() != 4 {
+            return None;
+        }
+        Some(Tile { x0: v[0], y0: v[1], x1: v[2], y1: v[3] })
+    }
+}
+
+impl TileOrder {
+    pub fn all() -> Vec<TileOrder> {
+        vec![TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TileOrder::Scanline => "scanline",
+            TileOrder::Spiral => "spiral",
+            TileOrder::Hilbert => "hilbert",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        TileOrder::all().into_iter().find(|o| o.name() == name)
+    }
+}
+
+// Split a width x height image into tiles of at most tile_size x
+// tile_size pixels, in the requested order. Tiles on the right and
+// bottom edges are clipped to the image.
+pub fn tiles(width: usize, height: usize, tile_size: usize, order: TileOrder) -> Vec<Tile> {
+    tiles_in(&Tile { x0: 0, y0: 0, x1: width, y1: height }, tile_size, order)
+}
+
+// The tiles covering a region of the image. The tiles are the ones the
+// whole image would be split into, clipped to the region, so that
+// pixels render the same as they would in the whole image.
+pub fn tiles_in(region: &Tile, tile_size: usize, order: TileOrder) -> Vec<Tile> {
+    let first_col = region.x0 / tile_size;
+    let first_row = region.y0 / tile_size;
+    let cols = (region.x1 + tile_size - 1) / tile_size - first_col;
+    let rows = (region.y1 + tile_size - 1) / tile_size - first_row;
+
+    let coords = match order {
+        TileOrder::Scanline => scanline_order(cols, rows),
+        TileOrder::Spiral => spiral_order(cols, rows),
+        TileOrder::Hilbert => hilbert_order(cols, rows),
+    };
+
+    coords.iter().map(|&(c, r)| Tile {
+        x0: max((first_col + c) * tile_size, region.x0),
+        y0: max((first_row + r) * tile_size, region.y0),
+        x1: min((first_col + c + 1) * tile_size, region.x1),
+        y1: min((first_row + r + 1) * tile_size, region.y1),
+    }).collect()
+}
+
+fn scanline_order(cols: usize, rows: usize) -> Vec<(usize, usize)> {
+    (0..rows).flat_map(|r| (0..cols).map(move |c| (c, r))).collect()
+}
+
+// Walk a square spiral out from the center tile, keeping the positions
+// that fall inside the grid.
+fn spiral_order(cols: usize, rows: usize) -> Vec<(usize, usize)> {
+    let total = cols * rows;
+    let mut result = Vec::with_capacity(total);
+    let (mut c, mut r) = ((cols as isize - 1) / 2, (rows as isize - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg_length = 1;
+    let mut direction = 0;
+
+    if total == 0 {
+        return result;
+    }
+
+    result.push((c as usize, r as usize));
+
+    while result.len() < total {
+        // Each leg length is used for two consecutive legs.
+        for _ in 0..2 {
+            let (dc, dr) = directions[direction];
+            for _ in 0..leg_length {
+                c += dc;
+                r += dr;
+                if c >= 0 && r >= 0 && (c as usize) < cols && (r as usize) < rows {
+                    result.push((c as usize, r as usize));
+                }
+            }
+            direction = (direction + 1) % 4;
+        }
+        leg_length += 1;
+    }
+
+    result
+}
+
+fn hilbert_order(cols: usize, rows: usize) -> Vec<(usize, usize)> {
+    let mut n = 1;
+    while n < cols || n < rows {
+        n *= 2;
+    }
+
+    let mut coords = scanline_order(cols, rows);
+    coords.sort_by_key(|&(c, r)| hilbert_index(n, c, r));
+    coords
+}
+
+// The distance of (x, y) along a Hilbert curve filling an n x n grid,
+// where n is a power of two.
+fn hilbert_index(n: usize, x: usize, y: usize) -> usize {
+    let (mut x, mut y) = (x, y);
+    let mut d = 0;
+    let mut s = n / 2;
+
+    while s > 0 {
+        let rx = if x & s > 0 { 1 } else { 0 };
+        let ry = if y & s > 0 { 1 } else { 0 };
+        d += s * s * ((3 * rx) ^ ry);
+
+        // Rotate the quadrant so the curve stays continuous.
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            let t = x;
+            x = y;
+            y = t;
+        }
+
+        s /= 2;
+    }
+
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Check that the tiles cover every pixel of the region exactly once,
+    // and that each lies within a single cell of the whole image's tile
+    // grid.
+    fn check_cover(region: &Tile, tile_size: usize, order: TileOrder) {
+        let tiles = tiles_in(region, tile_size, order);
+        let mut covered = vec![0; region.x1 * region.y1];
+
+        for t in tiles.iter() {
+            assert!(t.x0 < t.x1 && t.y0 < t.y1, "empty tile {:?}", t);
+            assert!(t.x0 >= region.x0 && t.x1 <= region.x1 &&
+                    t.y0 >= region.y0 && t.y1 <= region.y1, "tile {:?} outside region", t);
+            assert!(t.width() <= tile_size && t.height() <= tile_size);
+            assert_eq!(t.x0 / tile_size, (t.x1 - 1) / tile_size);
+            assert_eq!(t.y0 / tile_size, (t.y1 - 1) / tile_size);
+
+            for y in t.y0..t.y1 {
+                for x in t.x0..t.x1 {
+                    covered[y * region.x1 + x] += 1;
+                }
+            }
+        }
+
+        for y in 0..region.y1 {
+            for x in 0..region.x1 {
+                let inside = x >= region.x0 && y >= region.y0;
+                assert_eq!(covered[y * region.x1 + x], if inside { 1 } else { 0 },
+                           "pixel {},{} with {:?} tiles of {} in {:?}",
+                           x, y, order, tile_size, region);
+            }
+        }
+    }
+
+    #[test]
+    fn every_pixel_once() {
+        let sizes = [(64, 64), (100, 37), (37, 100), (800, 500), (1, 1), (5, 300), (33, 33)];
+        for order in TileOrder::all() {
+            for &(width, height) in sizes.iter() {
+                for &tile_size in [1, 7, 16, 32, 64, 1000].iter() {
+                    check_cover(&Tile { x0: 0, y0: 0, x1: width, y1: height }, tile_size, order);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn regions() {
+        let regions = [
+            Tile { x0: 10, y0: 20, x1: 90, y1: 30 },
+            Tile { x0: 31, y0: 31, x1: 33, y1: 33 },
+            Tile { x0: 0, y0: 5, x1: 7, y1: 100 },
+        ];
+        for order in TileOrder::all() {
+            for r in regions.iter() {
+                for &tile_size in [1, 8, 32].iter() {
+                    check_cover(r, tile_size, order);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn orders_have_the_same_tiles() {
+        let image = Tile { x0: 0, y0: 0, x1: 300, y1: 170 };
+        let key = |t: &Tile| (t.y0, t.x0);
+        let mut scanline = tiles_in(&image, 32, TileOrder::Scanline);
+        scanline.sort_by_key(key);
+        for order in TileOrder::all() {
+            let mut t = tiles_in(&image, 32, order);
+            t.sort_by_key(key);
+            assert_eq!(t, scanline);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 
 extern crate nalgebra;
-use nalgebra::{Vector3};
+use nalgebra::{Vector2, Vector3};
 
 extern crate rand;
 use self::rand::Rng;
@@ -14,24 +14,37 @@ use std::ops::MulAssign;
 use std::ops::Mul;
 use std::ops::Add;
 use std::cmp::Ordering;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+
+use tiles::{Tile, TileOrder};
+use accumulator::Accumulator;
+use constants::*;
+use animation::Animation;
+use bvh::{Aabb, Bvh};
+use stats::RayStats;
 
 pub struct MasterSampleSets {
     image_width: usize,
+    seed: u64,
     pub pixel_sets: Vec<Vec<samplers::UnitSquareSample>>,
-    pub disc_sets: Vec<Vec<samplers::UnitDiscSample>>,
+    pub lens_sets: Vec<Vec<samplers::UnitSquareSample>>,
     pub hemi_sets: Vec<Vec<Vec<Vector3<f64>>>>,
+    pub time_sets: Vec<Vec<f64>>,
 }
 
 impl MasterSampleSets {
-    pub fn new(sampler: &mut samplers::SampleSource, sample_root: usize,
-               max_depth: usize, width: usize) -> MasterSampleSets {
+    // All sample sets, and the shuffles of them, are derived from the
+    // seed, so the same seed always produces the same samples.
+    pub fn new(seed: u64, sample_root: usize, max_depth: usize, width: usize) -> MasterSampleSets {
+        let sampler = &mut samplers::seeded(seed);
+
         MasterSampleSets {
             pixel_sets: (0..width).map(|_|
                 samplers::u_grid_jittered(sampler, sample_root)).collect(),
 
-            disc_sets: (0..width).map(|_|
-                samplers::to_poisson_disc(
-                    samplers::u_grid_jittered(sampler, sample_root))).collect(),
+            lens_sets: (0..width).map(|_|
+                samplers::u_grid_jittered(sampler, sample_root)).collect(),
 
             hemi_sets: (0..width).map(|_|
                 (0..max_depth).map(|_|
@@ -41,14 +54,28 @@ impl MasterSampleSets {
                     ).collect()
                 ).collect(),
 
+            time_sets: (0..width).map(|_|
+                samplers::u_line_jittered(sampler, sample_root * sample_root)).collect(),
+
             image_width: width,
+            seed,
         }
     }
 
-    pub fn shuffle_indices(&self) -> Vec<usize> {
+    // Pick count distinct sample set indexes in random order. This is a
+    // partial Fisher-Yates shuffle, so tiles narrower than the image
+    // don't pay for shuffling a whole row. The order is determined by
+    // the seed and the keys, which identify the pixels being shuffled
+    // for.
+    pub fn shuffle_indices(&self, keys: &[u64], count: usize) -> Vec<usize> {
         let mut sample_set_indexes: Vec<usize> = (0..self.image_width).collect();
-        let mut sampler = samplers::new();
-        sampler.rng.shuffle(&mut sample_set_indexes);
+        let seed = keys.iter().fold(self.seed, |s, &k| samplers::derive_seed(s, k));
+        let mut sampler = samplers::seeded(seed);
+        for i in 0..count {
+            let j = sampler.rng.gen_range(i, self.image_width);
+            sample_set_indexes.swap(i, j);
+        }
+        sample_set_indexes.truncate(count);
         sample_set_indexes
     }
 }
@@ -58,6 +85,12 @@ pub struct Hit<'a> {
     pub distance: f64,
     pub point: Vector3<f64>,
     pub normal: Vector3<f64>,
+    // Texture coordinates of the hit point.
+    pub uv: Vector2<f64>,
+    // The surface color at the hit point, e.g. from mesh vertex colors,
+    // which materials multiply their own color by. White if the surface
+    // has no color of its own.
+    pub color: Color,
     pub material: &'a Material,
 }
 
@@ -69,6 +102,31 @@ pub struct Config {
     pub max_depth: usize,
     pub output_file: String,
     pub scene_name: String,
+    pub stats_file: Option<String>,
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    pub passes: Option<usize>,
+    pub seed: u64,
+    pub checkpoint_file: Option<String>,
+    pub checkpoint_interval: u64,
+    pub resume_file: Option<String>,
+    pub workers: Vec<String>,
+    // Seconds to wait for a worker to connect or reply before giving
+    // its tiles to the others.
+    pub worker_timeout: u64,
+    // Only render this part of the image. With crop set the output is
+    // just the region, otherwise the rest of the image is black.
+    pub region: Option<Tile>,
+    pub crop: bool,
+    // Render a sequence of frames with the camera moving, instead of a
+    // single image.
+    pub animation: Option<Animation>,
+    // Render with this camera instead of the scene's default camera.
+    pub camera_name: Option<String>,
+    // Render every camera of the scene, each to its own output files.
+    pub all_cameras: bool,
+    // The directory scenes load their model files from.
+    pub models_dir: String,
 }
 
 #[derive(Clone)]
@@ -89,6 +147,9 @@ pub struct Image {
 pub struct Ray {
     pub origin: Vector3<f64>,
     pub direction: Vector3<f64>,
+    // The time at which the ray was fired, in the same units as the
+    // camera shutter interval. Scattered rays keep their parent's time.
+    pub time: f64,
 }
 
 pub struct ViewPlane {
@@ -109,16 +170,44 @@ pub trait Material: Sync + Send {
 
 pub trait Intersectable: Sync + Send {
     fn hit<'a>(&'a self, r: &Ray) -> Option<Hit<'a>>;
+
+    // hit, also counting the intersection tests made in stats. Shapes
+    // made of many primitives, like meshes, count each one tested;
+    // others count as a single test.
+    fn hit_with_stats<'a>(&'a self, r: &Ray, stats: &mut RayStats) -> Option<Hit<'a>> {
+        stats.intersection_tests += 1;
+        self.hit(r)
+    }
+
+    // A box containing the shape, or None if it is unbounded.
+    fn bounds(&self) -> Option<Aabb>;
+
+    // Build any acceleration structure over the shape's own parts;
+    // Scene::build_bvh calls this before building its own.
+    fn build_bvh(&mut self) {}
 }
 
 pub struct Scene {
     pub objects: Vec<Box<Intersectable>>,
     pub background: Color,
+    // The camera rendered with, and its name.
     pub camera: Box<Camera>,
+    pub camera_name: String,
+    // The scene's other cameras by name; select_camera swaps one of
+    // them with the current camera.
+    pub cameras: Vec<(String, Box<Camera>)>,
+    // Built by build_bvh once the objects are in place, and rebuilt if
+    // they change. Without it every object is tested for every ray.
+    pub bvh: Option<Bvh>,
     pub config: Config,
     pub view_plane: ViewPlane,
+    // Problems found while building the scene that didn't stop it, such
+    // as a model's missing materials, for the program to report.
+    pub warnings: Vec<String>,
 }
 
+#[derive(Debug)]
+#[derive(Clone)]
 pub struct CameraCore {
     pub eye: Vector3<f64>,
     pub look_at: Vector3<f64>,
@@ -126,6 +215,11 @@ pub struct CameraCore {
     pub u: Vector3<f64>,
     pub v: Vector3<f64>,
     pub w: Vector3<f64>,
+    // The interval during which the shutter is open. Each sample's ray
+    // is fired at a time within it; new cores have both set to zero,
+    // which disables motion blur.
+    pub shutter_open: f64,
+    pub shutter_close: f64,
 }
 
 impl CameraCore {
@@ -135,11 +229,25 @@ impl CameraCore {
             u: Vector3::new(0.0, 0.0, 0.0),
             v: Vector3::new(0.0, 0.0, 0.0),
             w: Vector3::new(0.0, 0.0, 0.0),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
         };
         core.compute_uvw();
         core
     }
 
+    pub fn with_shutter(mut self, open: f64, close: f64) -> CameraCore {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    // The time for a sample at position t in [0..1] through the
+    // shutter interval.
+    pub fn shutter_time(&self, t: f64) -> f64 {
+        self.shutter_open + t * (self.shutter_close - self.shutter_open)
+    }
+
     pub fn compute_uvw(&mut self) {
         self.w = (self.eye - self.look_at).normalize();
         self.u = self.up.cross(&self.w).normalize();
@@ -147,10 +255,99 @@ impl CameraCore {
     }
 }
 
+// A single camera sample: a position on the view plane, in pixel units
+// relative to the center of the view plane with y pointing up, a
+// position in [0..1] x [0..1] for sampling the lens aperture, and a
+// position in [0..1] through the shutter interval.
+#[derive(Clone)]
+#[derive(Copy)]
+pub struct CameraSample {
+    pub px: f64,
+    pub py: f64,
+    pub lx: f64,
+    pub ly: f64,
+    pub t: f64,
+}
+
+// Cameras only generate primary rays; the pixel loop, sampling and
+// averaging are handled by render::render. A camera returns None for
+// samples outside the area it can image, such as the corners of a
+// fisheye view; those samples are black.
 pub trait Camera: Sync + Send {
-    fn render(&self, scene: &Scene) -> Image;
+    fn ray(&self, vp: &ViewPlane, s: &CameraSample) -> Option<Ray>;
+
+    // The camera's position and orientation. Setting it moves the
+    // camera, e.g. between animation frames.
+    fn core(&self) -> &CameraCore;
+    fn set_core(&mut self, core: CameraCore);
+
+    // Autofocus support; see Scene::autofocus. Cameras that focus
+    // return the ray to measure the focus distance along, and are then
+    // told the distance to the first hit along it.
+    fn focus_ray(&self, _vp: &ViewPlane) -> Option<Ray> {
+        None
+    }
+
+    fn focus_on(&mut self, _r: &Ray, _distance: f64) {
+    }
+
+    // The color of a sample, given a function that traces a primary
+    // ray. Cameras that combine several rays into one sample, like
+    // anaglyph stereo, override this.
+    fn sample_color(&self, vp: &ViewPlane, s: &CameraSample,
+                    trace: &mut FnMut(&Ray) -> Color) -> Color {
+        match self.ray(vp, s) {
+            Some(r) => trace(&r),
+            None => black(),
+        }
+    }
+}
+
+// A shared flag used to ask an in-flight render to stop. Clones refer
+// to the same flag, so one can be handed to the renderer while another
+// is kept by whoever wants to cancel it.
+#[derive(Clone)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken {
+            flag: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.flag.store(true, AtomicOrdering::SeqCst);
+    }
+
+    pub fn reset(&self) {
+        self.flag.store(false, AtomicOrdering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(AtomicOrdering::SeqCst)
+    }
+}
+
+// Receives notifications about the progress of a render. All methods
+// have empty default implementations so observers only need to
+// implement the ones they care about. Tiles may finish in any order.
+// Observers are given the accumulation buffer, locked, after each tile
+// is added to it (acc.tile_pixels() gives the tile's current estimate)
+// and after each pass. Passes are numbered from zero.
+pub trait RenderObserver: Sync + Send {
+    fn setting_up(&self) {}
+    fn render_started(&self) {}
+    fn tile_finished(&self, _tile: &Tile, _acc: &Accumulator) {}
+    fn pass_finished(&self, _pass: usize, _acc: &Accumulator) {}
 }
 
+pub struct NullObserver;
+
+impl RenderObserver for NullObserver {}
+
 impl Image {
     pub fn new(w: usize, h: usize) -> Image {
         Image {
@@ -164,6 +361,30 @@ impl Image {
         self.pixels[row_index] = values;
     }
 
+    // Copy in the pixels of a rendered tile, given row by row. Rows the
+    // tile touches are filled out with black first if they are still
+    // empty.
+    pub fn set_tile(&mut self, tile: &Tile, values: &Vec<Color>) {
+        for (i, tile_row) in values.chunks(tile.width()).enumerate() {
+            let row = &mut self.pixels[tile.y0 + i];
+            if row.len() < self.width {
+                row.resize(self.width, black());
+            }
+            row[tile.x0..tile.x1].copy_from_slice(tile_row);
+        }
+    }
+
+    // The part of the image inside the given region.
+    pub fn crop(&self, region: &Tile) -> Image {
+        let mut img = Image::new(region.width(), region.height());
+        for y in region.y0..region.y1 {
+            let row = &self.pixels[y];
+            img.set_row(y - region.y0, (region.x0..region.x1).map(|x|
+                if x < row.len() { row[x] } else { black() }).collect());
+        }
+        img
+    }
+
     pub fn write(&self, f: &mut File) {
         let mut buf = BufWriter::new(f);
 
@@ -284,6 +505,34 @@ impl<'a> Hit<'a> {
     }
 }
 
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            sample_root: DEFAULT_SAMPLE_ROOT,
+            quiet: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            output_file: String::from(DEFAULT_OUTPUT_FILENAME),
+            scene_name: String::new(),
+            stats_file: None,
+            tile_size: DEFAULT_TILE_SIZE,
+            tile_order: TileOrder::from_name(DEFAULT_TILE_ORDER).unwrap(),
+            passes: None,
+            seed: 0,
+            checkpoint_file: None,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            resume_file: None,
+            workers: vec![],
+            worker_timeout: DEFAULT_WORKER_TIMEOUT,
+            region: None,
+            crop: false,
+            animation: None,
+            camera_name: None,
+            all_cameras: false,
+            models_dir: String::from(DEFAULT_MODELS_DIR),
+        }
+    }
+}
+
 impl Config {
     pub fn show(&self) {
         println!("Renderer configuration:");
@@ -291,7 +540,47 @@ impl Config {
         println!("  Sample root:    {} ({} pixel sample{})",
            self.sample_root, self.sample_root * self.sample_root,
            if self.sample_root == 1 { "" } else { "s" });
+        if let Some(passes) = self.passes {
+            println!("  Passes:         {} (progressive)", passes);
+        }
         println!("  Maximum depth:  {}", self.max_depth);
+        println!("  Seed:           {}", self.seed);
+        println!("  Tiles:          {}x{}, {} order",
+           self.tile_size, self.tile_size, self.tile_order.name());
+        if let Some(r) = self.region {
+            println!("  Region:         {},{} to {},{}{}", r.x0, r.y0, r.x1, r.y1,
+                     if self.crop { " (cropped)" } else { "" });
+        }
+        if self.all_cameras {
+            println!("  Camera:         all");
+        } else if let Some(ref name) = self.camera_name {
+            println!("  Camera:         {}", name);
+        }
         println!("  Output path:    {}", self.output_file);
+        if let Some(ref anim) = self.animation {
+            println!("  Animation:      {}", anim.describe());
+        }
+        if let Some(ref path) = self.resume_file {
+            println!("  Resuming from:  {}", path);
+        }
+        if let Some(ref path) = self.checkpoint_file {
+            println!("  Checkpoint:     {} (every {} s)", path, self.checkpoint_interval);
+        }
+        if let Some(ref path) = self.stats_file {
+            println!("  Stats path:     {}", path);
+        }
+        if !self.workers.is_empty() {
+            println!("  Workers:        {} (timeout {} s)",
+                     self.workers.join(", "), self.worker_timeout);
+        }
+    }
+
+    // The image to write out: the whole image, or just the region if
+    // cropping.
+    pub fn output_image(&self, img: Image) -> Image {
+        match self.region {
+            Some(ref r) if self.crop => img.crop(r),
+            _ => img,
+        }
     }
 }
@@ -3,10 +3,25 @@ extern crate nalgebra;
 
 use nalgebra::{Vector3};
 
+use std::path::Path;
+
 pub fn reflect(v: &Vector3<f64>, n: &Vector3<f64>) -> Vector3<f64> {
     v - 2.0 * v.dot(&n) * n
 }
 
+// Insert a suffix into a file path before its extension, e.g.
+// output.ppm with suffix top becomes output.top.ppm.
+pub fn path_with_suffix(path: &str, suffix: &str) -> String {
+    let p = Path::new(path);
+    match (p.file_stem(), p.extension()) {
+        (Some(stem), Some(ext)) =>
+            p.with_file_name(format!("{}.{}.{}", stem.to_string_lossy(), suffix,
+                                     ext.to_string_lossy()))
+                .to_string_lossy().into_owned(),
+        _ => format!("{}.{}", path, suffix),
+    }
+}
+
 // fn refract(v: &Vector3<f64>, n: &Vector3<f64>, ni_nt: f64) -> Option<Vector3<f64>> {
 //     let uv = v.normalize();
 //     let dt = uv.dot(n);